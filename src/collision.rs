@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+
+use bevy::math::{DVec3, IVec3};
+
+use crate::physics::minimum_image;
+
+// bodies whose bounding box spans more cells than this skip the grid and are tested against everything
+// this keeps a few huge bodies from filling thousands of cells when sizes are very different
+const MAX_CELLS_PER_BODY: i64 = 64;
+
+fn cell_of(point: DVec3, cell_size: f64) -> IVec3 {
+    (point / cell_size).floor().as_ivec3()
+}
+
+fn overlaps(a: usize, b: usize, centers: &[DVec3], radii: &[f64], period: Option<f64>) -> bool {
+    minimum_image(centers[b] - centers[a], period).length_squared() <= (radii[a] + radii[b]).powi(2)
+}
+
+// find every pair of spheres that overlap using a uniform grid (spatial hash)
+// the cell size follows the median diameter so typical bodies touch at most eight cells
+// in a periodic box with the given side the grid wraps around too, so spheres touch across opposite faces
+// pairs are returned as (lower index, higher index), sorted, so the result is deterministic
+pub fn overlapping_pairs(
+    centers: &[DVec3],
+    radii: &[f64],
+    period: Option<f64>,
+) -> Vec<(usize, usize)> {
+    let n = centers.len();
+    if n < 2 {
+        return Vec::new();
+    }
+    let mut sorted_radii: Vec<f64> = radii.iter().copied().filter(|r| *r > 0.).collect();
+    if sorted_radii.is_empty() {
+        return Vec::new();
+    }
+    sorted_radii.sort_by(f64::total_cmp);
+    let mut cell_size = 2. * sorted_radii[sorted_radii.len() / 2];
+    // a whole number of cells fits across the box, so cells can wrap
+    let cells_across = period.map(|period| {
+        let cells = (period / cell_size).floor().max(1.);
+        cell_size = period / cells;
+        cells as i32
+    });
+    let wrap = |cell: IVec3| match cells_across {
+        Some(cells) => IVec3::new(
+            cell.x.rem_euclid(cells),
+            cell.y.rem_euclid(cells),
+            cell.z.rem_euclid(cells),
+        ),
+        None => cell,
+    };
+
+    let mut grid: HashMap<IVec3, Vec<usize>> = HashMap::new();
+    let mut large = Vec::new();
+    for i in 0..n {
+        if radii[i] <= 0. {
+            continue;
+        }
+        let min = cell_of(centers[i] - radii[i], cell_size);
+        let max = cell_of(centers[i] + radii[i], cell_size);
+        let span = (max - min + IVec3::ONE).as_i64vec3();
+        // a body reaching all the way around the box would land in the same cell twice
+        let wraps_around = cells_across.is_some_and(|cells| span.max_element() >= cells as i64);
+        if span.x * span.y * span.z > MAX_CELLS_PER_BODY || wraps_around {
+            large.push(i);
+            continue;
+        }
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    grid.entry(wrap(IVec3::new(x, y, z))).or_default().push(i);
+                }
+            }
+        }
+    }
+
+    let mut pairs = Vec::new();
+    for (cell, members) in &grid {
+        for (k, &a) in members.iter().enumerate() {
+            for &b in &members[k + 1..] {
+                // a pair sharing several cells is only reported from the cell holding the minimum corner of their overlap
+                // in a periodic box the corner isn't well defined, so duplicates are removed at the end instead
+                let corner = (centers[a] - radii[a]).max(centers[b] - radii[b]);
+                let reported_here = period.is_some() || cell_of(corner, cell_size) == *cell;
+                if reported_here && overlaps(a, b, centers, radii, period) {
+                    pairs.push((a.min(b), a.max(b)));
+                }
+            }
+        }
+    }
+    for (k, &a) in large.iter().enumerate() {
+        for b in 0..n {
+            // large pairs are found once, from the earlier of the two in the large list
+            if a == b || radii[b] <= 0. || large[..k].contains(&b) {
+                continue;
+            }
+            if overlaps(a, b, centers, radii, period) {
+                pairs.push((a.min(b), a.max(b)));
+            }
+        }
+    }
+    pairs.sort_unstable();
+    pairs.dedup();
+    pairs
+}
+
+// earliest fraction of the step in [0, 1] at which two spheres moving in straight lines touch
+// start and end are the two centers' offsets (b - a) at the start and end of the step
+pub fn time_of_contact(start: DVec3, end: DVec3, contact_distance: f64) -> Option<f64> {
+    // already touching at the start of the step
+    let c = start.length_squared() - contact_distance * contact_distance;
+    if c <= 0. {
+        return Some(0.);
+    }
+    // solve |start + t (end - start)| = contact_distance for the first root
+    let motion = end - start;
+    let a = motion.length_squared();
+    if a == 0. {
+        return None;
+    }
+    let b = 2. * start.dot(motion);
+    let discriminant = b * b - 4. * a * c;
+    if discriminant < 0. {
+        return None;
+    }
+    let t = (-b - discriminant.sqrt()) / (2. * a);
+    (0. ..=1.).contains(&t).then_some(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_to_pair() {
+        assert!(overlapping_pairs(&[], &[], None).is_empty());
+        assert!(overlapping_pairs(&[DVec3::ZERO], &[1.], None).is_empty());
+        // bodies without a radius are ignored
+        assert!(overlapping_pairs(&[DVec3::ZERO, DVec3::ZERO], &[0., 0.], None).is_empty());
+        assert!(overlapping_pairs(&[DVec3::ZERO, DVec3::ZERO], &[1., 0.], None).is_empty());
+    }
+
+    #[test]
+    fn touching_spheres_overlap() {
+        let centers = [DVec3::ZERO, DVec3::new(1., 0., 0.)];
+        assert_eq!(overlapping_pairs(&centers, &[0.5, 0.5], None), vec![(0, 1)]);
+        assert!(overlapping_pairs(&centers, &[0.5, 0.49], None).is_empty());
+    }
+
+    #[test]
+    fn pairs_across_cell_boundaries_are_found_once() {
+        // the cell size is the median diameter, 1, so these straddle the faces at x = 1 and at y = z = 0
+        let centers = [
+            DVec3::new(0.9, -0.05, -0.05),
+            DVec3::new(1.1, 0.05, 0.05),
+            DVec3::new(10., 10., 10.),
+        ];
+        assert_eq!(
+            overlapping_pairs(&centers, &[0.5, 0.5, 0.5], None),
+            vec![(0, 1)]
+        );
+    }
+
+    #[test]
+    fn large_bodies_skip_the_grid() {
+        let mut centers = vec![DVec3::ZERO];
+        let mut radii = vec![50.];
+        for i in 0..4 {
+            centers.push(DVec3::new(30. + 3. * i as f64, 0., 0.));
+            radii.push(0.5);
+        }
+        // the last small body is just outside the large one
+        centers.push(DVec3::new(51., 0., 0.));
+        radii.push(0.5);
+        assert_eq!(
+            overlapping_pairs(&centers, &radii, None),
+            vec![(0, 1), (0, 2), (0, 3), (0, 4)]
+        );
+    }
+
+    #[test]
+    fn pairs_are_sorted_with_the_lower_index_first() {
+        let centers: Vec<DVec3> = (0..6).map(|i| DVec3::new(0.8 * i as f64, 0., 0.)).collect();
+        assert_eq!(
+            overlapping_pairs(&centers, &[0.5; 6], None),
+            vec![(0, 1), (1, 2), (2, 3), (3, 4), (4, 5)]
+        );
+    }
+
+    #[test]
+    fn periodic_boxes_wrap_around() {
+        let centers = [DVec3::new(-4.9, 0., 0.), DVec3::new(4.9, 0., 0.)];
+        assert!(overlapping_pairs(&centers, &[0.5, 0.5], None).is_empty());
+        assert_eq!(
+            overlapping_pairs(&centers, &[0.5, 0.5], Some(10.)),
+            vec![(0, 1)]
+        );
+        // a body as big as the box meets the other through several images but is reported once
+        assert_eq!(
+            overlapping_pairs(&centers, &[6., 0.5], Some(10.)),
+            vec![(0, 1)]
+        );
+    }
+
+    #[test]
+    fn already_touching_at_the_start() {
+        let start = DVec3::new(1., 0., 0.);
+        assert_eq!(time_of_contact(start, start, 1.), Some(0.));
+        assert_eq!(time_of_contact(start, DVec3::new(5., 0., 0.), 2.), Some(0.));
+    }
+
+    #[test]
+    fn head_on_contact() {
+        let t = time_of_contact(DVec3::new(4., 0., 0.), DVec3::ZERO, 2.);
+        assert_eq!(t, Some(0.5));
+    }
+
+    #[test]
+    fn parallel_motion_never_touches() {
+        // moving together, so the offset doesn't change
+        let offset = DVec3::new(5., 0., 0.);
+        assert_eq!(time_of_contact(offset, offset, 2.), None);
+        // passing side by side, closest at 3 apart
+        let t = time_of_contact(DVec3::new(5., 3., 0.), DVec3::new(-5., 3., 0.), 2.);
+        assert_eq!(t, None);
+    }
+
+    #[test]
+    fn grazing_contact() {
+        let t = time_of_contact(DVec3::new(5., 2., 0.), DVec3::new(-5., 2., 0.), 2.);
+        assert_eq!(t, Some(0.5));
+    }
+
+    #[test]
+    fn no_contact_outside_the_step() {
+        // moving apart
+        assert_eq!(
+            time_of_contact(DVec3::new(3., 0., 0.), DVec3::new(5., 0., 0.), 2.),
+            None
+        );
+        // would touch after the step ends
+        assert_eq!(
+            time_of_contact(DVec3::new(10., 0., 0.), DVec3::new(5., 0., 0.), 2.),
+            None
+        );
+        // reaches contact exactly at the end of the step
+        assert_eq!(
+            time_of_contact(DVec3::new(4., 0., 0.), DVec3::new(2., 0., 0.), 2.),
+            Some(1.)
+        );
+    }
+}
@@ -1,15 +1,60 @@
-use bevy::prelude::*;
+use bevy::{
+    math::{DQuat, DVec3},
+    prelude::*,
+};
 
 #[derive(Component, Clone, Copy)]
 pub struct Body {
-    pub mass: f32,
+    pub mass: f64,
+    // relative to the default, a body of mass 1 and density 1 has the default sphere radius
+    pub density: f64,
 }
 
+// simulation state is kept in double precision; Transform is only used for rendering
 #[derive(Component, Clone, Copy)]
-pub struct Position(pub Vec3);
+pub struct Position(pub DVec3);
 
 #[derive(Component, Clone, Copy)]
-pub struct Velocity(pub Vec3);
+pub struct Velocity(pub DVec3);
+
+// a massless body that is pulled by the others but pulls on nothing and never merges, for tracing out the field
+// every body with zero mass is one
+#[derive(Component, Clone, Copy)]
+pub struct TestParticle;
+
+// angular velocity about the body's center, in radians per second
+#[derive(Component, Clone, Copy)]
+pub struct Spin(pub DVec3);
+
+// acceleration at the end of the last physics step, which starts the next one
+// a body that was just spawned or merged has none until its forces are first summed
+#[derive(Component, Clone, Copy, Default)]
+pub struct Acceleration(pub DVec3);
+
+// a fragment of a tidal disruption, which isn't broken up again until it has left the Roche limit it broke up in
+#[derive(Component, Clone, Copy)]
+pub struct TidalFragment;
+
+// electric charge, bodies without one are neutral
+#[derive(Component, Clone, Copy)]
+pub struct Charge(pub f64);
+
+// how far the body has turned, only used to show its spin
+#[derive(Component, Clone, Copy)]
+pub struct Orientation(pub DQuat);
+
+// position at the start of the last physics step, used to sweep bodies for collisions
+#[derive(Component, Clone, Copy)]
+pub struct PreviousPosition(pub DVec3);
+
+// position of the camera in simulation space
+// the camera's Transform stays at the render origin (floating origin), so everything is drawn relative to this
+#[derive(Component, Clone, Copy)]
+pub struct CameraPosition(pub DVec3);
+
+// marks the body chosen as the target for orbit spawning
+#[derive(Component, Clone, Copy)]
+pub struct Selected;
 
 // marks spawn display
 #[derive(Component, Clone, Copy)]
@@ -24,3 +69,40 @@ pub struct HelpText;
 
 #[derive(Component, Clone, Copy)]
 pub struct HelpUI;
+
+// marks the collision log UI
+#[derive(Component, Clone, Copy)]
+pub struct CollisionLogUI;
+
+// marks the collision log text
+#[derive(Component, Clone, Copy)]
+pub struct CollisionLogText;
+
+// a short lived flash where two bodies merged
+#[derive(Component, Clone, Copy)]
+pub struct ImpactFlash {
+    pub position: DVec3,
+    pub radius: f32,
+    // seconds since the flash appeared
+    pub age: f32,
+}
+
+// marks the timeline of recent states
+#[derive(Component, Clone, Copy)]
+pub struct TimelineUI;
+
+// marks the timeline text
+#[derive(Component, Clone, Copy)]
+pub struct TimelineText;
+
+// marks the filled part of the timeline bar
+#[derive(Component, Clone, Copy)]
+pub struct TimelineFill;
+
+// marks the gravitational wave overlay UI
+#[derive(Component, Clone, Copy)]
+pub struct GravitationalWaveUI;
+
+// marks the gravitational wave overlay text
+#[derive(Component, Clone, Copy)]
+pub struct GravitationalWaveText;
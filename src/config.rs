@@ -1,31 +1,433 @@
 use bevy::prelude::Resource;
 use serde::{Deserialize, Serialize};
 
+use crate::units::{UnitSystem, SCENE_GRAVITY_CONSTANT};
+
+// fields missing from a config file keep their default value
 #[derive(Clone, Resource, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Configuration {
-    pub gravity_constant: f32,
+    // what the simulation's lengths, masses and times stand for, see UnitSystem
+    // everything else in the configuration is in these units, and the defaults are scaled to the unit system's scene
+    pub units: UnitSystem,
+    // derived from the unit system unless it is simulation units, as are the coulomb constant and speed of light
+    pub gravity_constant: f64,
+    // strength of the electric force between charged bodies
+    pub coulomb_constant: f64,
+    // how gravity falls off with distance, see GravityLaw
+    pub gravity_law: GravityLaw,
+    // the n of the power law
+    pub gravity_power: f64,
+    // distance over which Yukawa gravity is screened
+    pub yukawa_length: f64,
+    // acceleration below which MOND gravity departs from Newtonian
+    pub mond_acceleration: f64,
+    // used by the relativistic corrections
+    pub speed_of_light: f64,
+    // add the first post-Newtonian (Einstein-Infeld-Hoffmann) terms to gravity
+    pub post_newtonian: bool,
+    // add the 2.5 post-Newtonian radiation reaction, so close pairs lose energy to gravitational waves
+    pub radiation_reaction: bool,
+    // break bodies into fragments inside the Roche limit of a body at least tidal_mass_ratio times heavier
+    pub tidal_disruption: bool,
+    pub tidal_mass_ratio: f64,
+    pub tidal_fragments: usize,
+    // bodies whose fragments would be lighter than this hold together
+    pub tidal_fragment_min_mass: f64,
+    // fixed background fields every body and test particle is accelerated by
+    pub external_potentials: Vec<ExternalPotential>,
+    // drag from a medium the bodies move through, see Drag
+    pub drag: Drag,
+    pub drag_coefficient: f64,
+    // only drag inside the atmospheres of bodies at least atmosphere_min_mass heavy, instead of everywhere
+    pub drag_atmosphere: bool,
+    pub atmosphere_min_mass: f64,
+    // height above a body's surface over which its atmosphere thins by a factor of e
+    pub atmosphere_scale_height: f64,
+    // what happens at the edge of the simulated space, see Boundary
+    pub boundary: Boundary,
+    // half the side of the box for the box boundaries, the radius of the escape sphere for escape
+    pub boundary_size: f64,
+    // test particles have no mass, and so no size, so they are all drawn this big
+    pub test_particle_radius: f32,
+    // length of the strain waveform shown for the selected pair
+    pub waveform_samples: usize,
     pub mouse_sensitivity: f32,
     pub camera_speed: f32,
     pub spawn_size_mousewheel_sensitivity: f32,
     pub spawn_speed_mousewheel_sensitivity: f32,
+    pub spawn_mass_mousewheel_sensitivity: f32,
+    pub spawn_density_mousewheel_sensitivity: f32,
+    pub spawn_count_mousewheel_sensitivity: f32,
+    pub spawn_spread_mousewheel_sensitivity: f32,
+    pub spawn_dispersion_mousewheel_sensitivity: f32,
+    pub spawn_eccentricity_mousewheel_sensitivity: f32,
+    pub spawn_charge_mousewheel_sensitivity: f32,
     pub spawn_speed_max: f32,
     pub spawn_size_max: f32,
+    pub spawn_density_max: f32,
+    pub spawn_count_max: f32,
+    pub spawn_spread_max: f32,
+    pub spawn_dispersion_max: f32,
+    pub spawn_eccentricity_max: f32,
+    // spawn charge goes from minus to plus this
+    pub spawn_charge_max: f32,
+    // bodies per second while streaming
+    pub stream_rate: f64,
+    // how far in front of the camera the slingshot places bodies
+    pub slingshot_distance: f64,
+    // launch velocity per pixel of mouse drag and per line of scrolling
+    pub slingshot_drag_sensitivity: f32,
+    pub slingshot_scroll_sensitivity: f32,
     pub time_rate_sensitivity: f32,
     pub speed_mod_factor: f32,
+    // number of chunks the force evaluation is split into, 0 uses every compute thread
+    pub force_threads: usize,
+    // also time the old per pair query loop and report pair interactions per second for both
+    pub benchmark: bool,
+    // show the system's total energy on the display, it costs a pass over every pair a few times a second
+    pub show_energy: bool,
+    // number of collisions kept in the on-screen log
+    pub collision_log_lines: usize,
+    // if set, every collision is appended to this file as a line of json
+    pub collision_log_file: Option<String>,
+    // number of world edits that can be undone
+    pub undo_history_length: usize,
+    // simulated time per real second at normal time speed
+    pub time_scale: f64,
+    // length of a single step taken while paused
+    pub single_step_size: f64,
+    // number of recent physics steps kept to rewind through
+    pub rewind_length: usize,
+    // most body states kept across all of those steps, older steps are dropped first to stay under it
+    pub rewind_body_limit: usize,
 }
 
 impl Default for Configuration {
     fn default() -> Self {
         Self {
-            gravity_constant: 8.,
+            units: UnitSystem::Simulation,
+            gravity_constant: SCENE_GRAVITY_CONSTANT,
+            coulomb_constant: 8.,
+            gravity_law: GravityLaw::Newtonian,
+            gravity_power: 2.5,
+            yukawa_length: 10.,
+            mond_acceleration: 0.1,
+            speed_of_light: 20.,
+            post_newtonian: false,
+            radiation_reaction: false,
+            tidal_disruption: false,
+            tidal_mass_ratio: 10.,
+            tidal_fragments: 8,
+            tidal_fragment_min_mass: 0.05,
+            external_potentials: Vec::new(),
+            drag: Drag::None,
+            drag_coefficient: 0.05,
+            drag_atmosphere: false,
+            atmosphere_min_mass: 10.,
+            atmosphere_scale_height: 1.,
+            boundary: Boundary::None,
+            boundary_size: 100.,
+            test_particle_radius: 0.05,
+            waveform_samples: 300,
             mouse_sensitivity: 0.002,
             camera_speed: 5.,
             spawn_size_mousewheel_sensitivity: 0.05,
             spawn_speed_mousewheel_sensitivity: 0.05,
+            spawn_mass_mousewheel_sensitivity: 0.1,
+            spawn_density_mousewheel_sensitivity: 0.05,
+            spawn_count_mousewheel_sensitivity: 1.,
+            spawn_spread_mousewheel_sensitivity: 0.1,
+            spawn_dispersion_mousewheel_sensitivity: 0.05,
+            spawn_eccentricity_mousewheel_sensitivity: 0.05,
+            spawn_charge_mousewheel_sensitivity: 0.1,
             spawn_speed_max: 20.,
             spawn_size_max: 5.,
+            spawn_density_max: 20.,
+            spawn_count_max: 50.,
+            spawn_spread_max: 50.,
+            spawn_dispersion_max: 10.,
+            spawn_eccentricity_max: 3.,
+            spawn_charge_max: 10.,
+            stream_rate: 10.,
+            slingshot_distance: 5.,
+            slingshot_drag_sensitivity: 0.01,
+            slingshot_scroll_sensitivity: 0.5,
             time_rate_sensitivity: 0.1,
             speed_mod_factor: 5.,
+            force_threads: 0,
+            benchmark: false,
+            show_energy: false,
+            collision_log_lines: 8,
+            collision_log_file: None,
+            undo_history_length: 100,
+            time_scale: 1.,
+            single_step_size: 0.02,
+            rewind_length: 600,
+            rewind_body_limit: 200_000,
+        }
+    }
+}
+
+impl Configuration {
+    // side length of the box when space wraps around
+    pub fn period(&self) -> Option<f64> {
+        (self.boundary == Boundary::Periodic).then_some(2. * self.boundary_size)
+    }
+
+    // the defaults with every length, mass and time in them scaled from simulation units to the unit system's scene,
+    // so the built-in scene and the spawning controls work the same in any unit system
+    pub fn for_units(units: UnitSystem) -> Self {
+        let (length, mass, time) = units.scene();
+        let speed = length / time;
+        let defaults = Self::default();
+        // the charge whose repulsion matches the pull of a scene mass
+        let charge = mass
+            * (units
+                .gravity_constant()
+                .unwrap_or(defaults.gravity_constant)
+                / units
+                    .coulomb_constant()
+                    .unwrap_or(defaults.coulomb_constant))
+            .sqrt();
+        Self {
+            units,
+            yukawa_length: defaults.yukawa_length * length,
+            mond_acceleration: defaults.mond_acceleration * speed / time,
+            tidal_fragment_min_mass: defaults.tidal_fragment_min_mass * mass,
+            // per unit time as linear drag, quadratic drag reads it per unit length
+            drag_coefficient: defaults.drag_coefficient / time,
+            atmosphere_min_mass: defaults.atmosphere_min_mass * mass,
+            atmosphere_scale_height: defaults.atmosphere_scale_height * length,
+            boundary_size: defaults.boundary_size * length,
+            test_particle_radius: defaults.test_particle_radius * length as f32,
+            camera_speed: defaults.camera_speed * length as f32,
+            spawn_size_mousewheel_sensitivity: defaults.spawn_size_mousewheel_sensitivity
+                * length as f32,
+            spawn_speed_mousewheel_sensitivity: defaults.spawn_speed_mousewheel_sensitivity
+                * speed as f32,
+            spawn_mass_mousewheel_sensitivity: defaults.spawn_mass_mousewheel_sensitivity
+                * mass as f32,
+            spawn_density_mousewheel_sensitivity: defaults.spawn_density_mousewheel_sensitivity
+                * (mass / length.powi(3)) as f32,
+            spawn_spread_mousewheel_sensitivity: defaults.spawn_spread_mousewheel_sensitivity
+                * length as f32,
+            spawn_dispersion_mousewheel_sensitivity: defaults
+                .spawn_dispersion_mousewheel_sensitivity
+                * speed as f32,
+            spawn_charge_mousewheel_sensitivity: defaults.spawn_charge_mousewheel_sensitivity
+                * charge as f32,
+            spawn_speed_max: defaults.spawn_speed_max * speed as f32,
+            spawn_size_max: defaults.spawn_size_max * length as f32,
+            spawn_density_max: defaults.spawn_density_max * (mass / length.powi(3)) as f32,
+            spawn_spread_max: defaults.spawn_spread_max * length as f32,
+            spawn_dispersion_max: defaults.spawn_dispersion_max * speed as f32,
+            spawn_charge_max: defaults.spawn_charge_max * charge as f32,
+            slingshot_distance: defaults.slingshot_distance * length,
+            slingshot_drag_sensitivity: defaults.slingshot_drag_sensitivity * speed as f32,
+            slingshot_scroll_sensitivity: defaults.slingshot_scroll_sensitivity * speed as f32,
+            time_scale: defaults.time_scale * time,
+            single_step_size: defaults.single_step_size * time,
+            ..defaults
+        }
+    }
+
+    // a config file on top of the defaults for its unit system
+    pub fn from_json(data: &str) -> serde_json::Result<Self> {
+        let mut file: serde_json::Map<String, serde_json::Value> = serde_json::from_str(data)?;
+        let units = match file.get("units") {
+            Some(units) => serde_json::from_value(units.clone())?,
+            None => UnitSystem::default(),
+        };
+        let serde_json::Value::Object(mut merged) = serde_json::to_value(Self::for_units(units))?
+        else {
+            unreachable!("the configuration serializes to an object");
+        };
+        merged.append(&mut file);
+        serde_json::from_value(serde_json::Value::Object(merged))
+    }
+
+    // replace the constants with their values in a physical unit system
+    pub fn derive_constants(&mut self) {
+        if let Some(gravity_constant) = self.units.gravity_constant() {
+            self.gravity_constant = gravity_constant;
+        }
+        if let Some(speed_of_light) = self.units.speed_of_light() {
+            self.speed_of_light = speed_of_light;
+        }
+        if let Some(coulomb_constant) = self.units.coulomb_constant() {
+            self.coulomb_constant = coulomb_constant;
+        }
+    }
+}
+
+// a fixed background field, written in config files as an object with its "type" and parameters
+// centers are [x, y, z] points in simulation space
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExternalPotential {
+    // the same acceleration everywhere
+    Uniform {
+        acceleration: [f64; 3],
+    },
+    // a body that is held in place and never merges
+    PointMass {
+        mass: f64,
+        center: [f64; 3],
+    },
+    // dark matter halo with a flat rotation curve of the given circular velocity outside the core
+    LogarithmicHalo {
+        velocity: f64,
+        core_radius: f64,
+        center: [f64; 3],
+    },
+    // Navarro-Frenk-White dark matter halo, mass is 4 pi times the density scale times the scale radius cubed
+    NfwHalo {
+        mass: f64,
+        scale_radius: f64,
+        center: [f64; 3],
+    },
+    // Miyamoto-Nagai galactic disk lying in the xy plane, with radial and vertical scale lengths
+    MiyamotoNagai {
+        mass: f64,
+        radial_scale: f64,
+        vertical_scale: f64,
+        center: [f64; 3],
+    },
+    // pulls back towards the center in proportion to the distance, oscillating at the given angular frequency
+    HarmonicTrap {
+        frequency: f64,
+        center: [f64; 3],
+    },
+}
+
+// the attraction between two bodies at distance r, of which only the inverse square gives closed orbits
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GravityLaw {
+    // G m / r^2
+    #[default]
+    Newtonian,
+    // G m / r^n
+    PowerLaw,
+    // inverse square screened beyond the Yukawa length, from the potential -G m e^(-r / length) / r
+    Yukawa,
+    // Newtonian at strong fields, but falling off as 1 / r once the field is weaker than the MOND acceleration
+    Mond,
+}
+
+impl GravityLaw {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Newtonian => Self::PowerLaw,
+            Self::PowerLaw => Self::Yukawa,
+            Self::Yukawa => Self::Mond,
+            Self::Mond => Self::Newtonian,
         }
     }
 }
+
+// how a medium slows bodies moving through it, relative to the medium
+// the medium is at rest, or moves with the body whose atmosphere it is
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Drag {
+    #[default]
+    None,
+    // deceleration of the drag coefficient times the density times the speed
+    Linear,
+    // deceleration of the drag coefficient times the density times the speed squared
+    Quadratic,
+}
+
+impl Drag {
+    pub fn next(self) -> Self {
+        match self {
+            Self::None => Self::Linear,
+            Self::Linear => Self::Quadratic,
+            Self::Quadratic => Self::None,
+        }
+    }
+}
+
+// the edge of the simulated space, a box or sphere centered on the origin
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Boundary {
+    // space goes on forever
+    #[default]
+    None,
+    // bodies bounce elastically off the walls of the box
+    Reflecting,
+    // bodies leaving the box come back in the opposite side, and gravity acts through the nearest image of each body
+    Periodic,
+    // bodies leaving the sphere are removed, sending an escape event
+    Escape,
+}
+
+impl Boundary {
+    pub fn next(self) -> Self {
+        match self {
+            Self::None => Self::Reflecting,
+            Self::Reflecting => Self::Periodic,
+            Self::Periodic => Self::Escape,
+            Self::Escape => Self::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_scaled(config: &Configuration, units: UnitSystem) {
+        let (length, _, time) = units.scene();
+        let defaults = Configuration::default();
+        assert!(config.units == units);
+        assert_eq!(config.boundary_size, defaults.boundary_size * length);
+        assert_eq!(
+            config.slingshot_distance,
+            defaults.slingshot_distance * length
+        );
+        assert_eq!(config.time_scale, defaults.time_scale * time);
+        assert_eq!(config.drag_coefficient, defaults.drag_coefficient / time);
+    }
+
+    #[test]
+    fn defaults_scale_with_the_scene() {
+        let (length, _, time) = UnitSystem::Si.scene();
+        assert!(length > 1. && time > 1.);
+        assert_scaled(&Configuration::for_units(UnitSystem::Si), UnitSystem::Si);
+        assert_scaled(
+            &Configuration::for_units(UnitSystem::Simulation),
+            UnitSystem::Simulation,
+        );
+    }
+
+    #[test]
+    fn files_merge_over_the_scaled_defaults() {
+        let config = Configuration::from_json(r#"{"units": "si"}"#).unwrap();
+        assert_scaled(&config, UnitSystem::Si);
+        // what the file does say is kept as it is
+        let config =
+            Configuration::from_json(r#"{"units": "astronomical", "boundary_size": 3.0}"#).unwrap();
+        assert_eq!(config.boundary_size, 3.);
+        assert_eq!(
+            config.time_scale,
+            UnitSystem::Astronomical.scene().2 * Configuration::default().time_scale
+        );
+    }
+
+    // the shipped file only sets what has no units, so switching its unit system still scales everything else
+    #[test]
+    fn shipped_file_follows_its_units() {
+        let shipped = include_str!("../config/default_conf.json");
+        assert_scaled(
+            &Configuration::from_json(shipped).unwrap(),
+            UnitSystem::Simulation,
+        );
+        let si = shipped.replace(r#""units": "simulation""#, r#""units": "si""#);
+        assert!(si != shipped);
+        assert_scaled(&Configuration::from_json(&si).unwrap(), UnitSystem::Si);
+    }
+}
@@ -0,0 +1,48 @@
+use bevy::{math::DVec3, prelude::*};
+
+// sent by resolve_body_collisions for every merge
+#[derive(Event, Clone, Copy)]
+pub struct CollisionEvent {
+    // simulated time of the collision
+    pub time: f64,
+    // the body that remains after the merge and the one that was despawned
+    pub survivor: Entity,
+    pub absorbed: Entity,
+    // masses before the merge
+    pub survivor_mass: f64,
+    pub absorbed_mass: f64,
+    pub relative_speed: f64,
+    // kinetic energy lost to the merge (that of the relative motion)
+    pub impact_energy: f64,
+    // point on the survivor's surface where the bodies touched
+    pub location: DVec3,
+}
+
+impl CollisionEvent {
+    // one line json record for the collision log file
+    pub fn json_line(&self) -> String {
+        serde_json::json!({
+            "time": self.time,
+            "survivor": self.survivor.to_bits(),
+            "absorbed": self.absorbed.to_bits(),
+            "survivor_mass": self.survivor_mass,
+            "absorbed_mass": self.absorbed_mass,
+            "relative_speed": self.relative_speed,
+            "impact_energy": self.impact_energy,
+            "location": [self.location.x, self.location.y, self.location.z],
+        })
+        .to_string()
+    }
+}
+
+// sent when a body leaves the escape boundary and is removed
+#[derive(Event, Clone, Copy)]
+pub struct EscapeEvent {
+    // simulated time of the escape
+    pub time: f64,
+    pub body: Entity,
+    pub mass: f64,
+    // where and how fast the body was going as it left
+    pub position: DVec3,
+    pub velocity: DVec3,
+}
@@ -0,0 +1,644 @@
+use bevy::{math::DVec3, prelude::*};
+
+use crate::{
+    components::Body,
+    config::{Configuration, GravityLaw},
+    helpers::moment_of_inertia,
+    physics::{
+        external_acceleration, external_potential, field_accelerations, gravity_accelerations,
+        minimum_image, par_map_bodies, post_newtonian_accelerations, post_newtonian_pair,
+        radiation_reaction_accelerations, radiation_reaction_pair, BodyBuffer,
+    },
+    resources::{BodyState, ForceLaws},
+};
+
+// a force every body exerts on every other body, given as the acceleration it causes
+// register one with App::add_pair_force and it is summed into every body's acceleration each step,
+// test particles feel it from the bodies but don't exert it
+// this isn't a public API, the simulation is a binary only, so the laws all live in this module
+pub trait PairForce: Send + Sync + 'static {
+    // acceleration of body on caused by body from
+    fn acceleration(&self, on: &BodyState, from: &BodyState, config: &Configuration) -> DVec3;
+
+    // acceleration of every body from all the others, in order
+    // buffer holds the same bodies gathered for the fast kernels, so a law can override this with one
+    fn accelerations(
+        &self,
+        _buffer: &BodyBuffer,
+        bodies: &[BodyState],
+        config: &Configuration,
+    ) -> Vec<DVec3> {
+        pairwise_accelerations(self, bodies, config)
+    }
+
+    // acceleration of each test particle from all the bodies
+    fn field_accelerations(
+        &self,
+        _buffer: &BodyBuffer,
+        bodies: &[BodyState],
+        particles: &[BodyState],
+        config: &Configuration,
+    ) -> Vec<DVec3> {
+        pairwise_field_accelerations(self, bodies, particles, config)
+    }
+
+    // potential energy of a pair of bodies, for the energy shown on screen
+    // None if the law has none, like a velocity dependent or dissipative one
+    fn potential_energy(
+        &self,
+        _a: &BodyState,
+        _b: &BodyState,
+        _config: &Configuration,
+    ) -> Option<f64> {
+        None
+    }
+}
+
+// the default PairForce::accelerations, summing the law over every pair
+pub fn pairwise_accelerations(
+    law: &(impl PairForce + ?Sized),
+    bodies: &[BodyState],
+    config: &Configuration,
+) -> Vec<DVec3> {
+    par_map_bodies(bodies.len(), config.force_threads, |i| {
+        bodies
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| i != *j)
+            .map(|(_, from)| law.acceleration(&bodies[i], from, config))
+            .sum()
+    })
+}
+
+// the default PairForce::field_accelerations
+pub fn pairwise_field_accelerations(
+    law: &(impl PairForce + ?Sized),
+    bodies: &[BodyState],
+    particles: &[BodyState],
+    config: &Configuration,
+) -> Vec<DVec3> {
+    par_map_bodies(particles.len(), config.force_threads, |i| {
+        bodies
+            .iter()
+            .map(|from| law.acceleration(&particles[i], from, config))
+            .sum()
+    })
+}
+
+// a force acting on each body (and test particle) on its own, given as the acceleration it causes
+// register one with App::add_body_force
+pub trait BodyForce: Send + Sync + 'static {
+    fn acceleration(&self, body: &BodyState, config: &Configuration) -> DVec3;
+
+    // potential energy of a body in the field, None if there is none
+    fn potential_energy(&self, _body: &BodyState, _config: &Configuration) -> Option<f64> {
+        None
+    }
+}
+
+// gravity under the configuration's gravity law
+// inverse square gravity is evaluated with the vectorized kernel (nearest images in a periodic box), and MOND builds on it
+pub struct Gravity;
+
+// MOND's boost of the Newtonian field strength g, using the simple interpolating function
+fn mond_boost(g: DVec3, acceleration_scale: f64) -> DVec3 {
+    let y = g.length() / acceleration_scale;
+    if y == 0. {
+        return g;
+    }
+    g * (0.5 + (0.25 + 1. / y).sqrt())
+}
+
+impl PairForce for Gravity {
+    fn acceleration(&self, on: &BodyState, from: &BodyState, config: &Configuration) -> DVec3 {
+        let separation = minimum_image(from.position - on.position, config.period());
+        let r = separation.length();
+        if r == 0. {
+            return DVec3::ZERO;
+        }
+        let gm = config.gravity_constant * from.body.mass;
+        let direction = separation / r;
+        match config.gravity_law {
+            GravityLaw::Newtonian => gm / (r * r) * direction,
+            GravityLaw::PowerLaw => gm / r.powf(config.gravity_power) * direction,
+            GravityLaw::Yukawa => {
+                let x = r / config.yukawa_length;
+                gm * (-x).exp() * (1. + x) / (r * r) * direction
+            }
+            GravityLaw::Mond => mond_boost(gm / (r * r) * direction, config.mond_acceleration),
+        }
+    }
+
+    fn accelerations(
+        &self,
+        buffer: &BodyBuffer,
+        bodies: &[BodyState],
+        config: &Configuration,
+    ) -> Vec<DVec3> {
+        match config.gravity_law {
+            GravityLaw::Newtonian => {
+                gravity_accelerations(buffer, config.gravity_constant, config.force_threads)
+            }
+            // MOND isn't pairwise, the boost depends on the total Newtonian field at each body
+            GravityLaw::Mond => {
+                gravity_accelerations(buffer, config.gravity_constant, config.force_threads)
+                    .into_iter()
+                    .map(|g| mond_boost(g, config.mond_acceleration))
+                    .collect()
+            }
+            GravityLaw::PowerLaw | GravityLaw::Yukawa => {
+                pairwise_accelerations(self, bodies, config)
+            }
+        }
+    }
+
+    fn field_accelerations(
+        &self,
+        buffer: &BodyBuffer,
+        bodies: &[BodyState],
+        particles: &[BodyState],
+        config: &Configuration,
+    ) -> Vec<DVec3> {
+        let points: Vec<DVec3> = particles.iter().map(|p| p.position).collect();
+        let newtonian = || {
+            field_accelerations(
+                buffer,
+                &points,
+                config.gravity_constant,
+                config.force_threads,
+            )
+        };
+        match config.gravity_law {
+            GravityLaw::Newtonian => newtonian(),
+            GravityLaw::Mond => newtonian()
+                .into_iter()
+                .map(|g| mond_boost(g, config.mond_acceleration))
+                .collect(),
+            GravityLaw::PowerLaw | GravityLaw::Yukawa => {
+                pairwise_field_accelerations(self, bodies, particles, config)
+            }
+        }
+    }
+
+    fn potential_energy(
+        &self,
+        a: &BodyState,
+        b: &BodyState,
+        config: &Configuration,
+    ) -> Option<f64> {
+        let r = minimum_image(b.position - a.position, config.period()).length();
+        if r == 0. {
+            return Some(0.);
+        }
+        let gmm = config.gravity_constant * a.body.mass * b.body.mass;
+        match config.gravity_law {
+            GravityLaw::Newtonian => Some(-gmm / r),
+            GravityLaw::PowerLaw if config.gravity_power == 1. => Some(gmm * r.ln()),
+            GravityLaw::PowerLaw => {
+                let n = config.gravity_power - 1.;
+                Some(-gmm / (n * r.powf(n)))
+            }
+            GravityLaw::Yukawa => Some(-gmm * (-r / config.yukawa_length).exp() / r),
+            // MOND conserves no energy that is a sum over pairs
+            GravityLaw::Mond => None,
+        }
+    }
+}
+
+// speed of a circular orbit at the given offset from a body of the given mass under the configured gravity law,
+// from v^2 = r |a(r)|, the mass being the pair's combined mass so it holds for the relative orbit
+pub fn circular_speed(mass: f64, offset: DVec3, config: &Configuration) -> f64 {
+    let state = |mass, position| BodyState {
+        body: Body { mass, density: 1. },
+        position,
+        velocity: DVec3::ZERO,
+        spin: DVec3::ZERO,
+        charge: 0.,
+    };
+    let pull = Gravity.acceleration(&state(0., offset), &state(mass, DVec3::ZERO), config);
+    (offset.length() * pull.length()).sqrt()
+}
+
+// general relativity's weak field limit is Newtonian gravity, so its corrections only go on top of that
+fn relativistic(config: &Configuration) -> bool {
+    config.gravity_law == GravityLaw::Newtonian
+}
+
+// the first post-Newtonian (Einstein-Infeld-Hoffmann) correction to gravity, while the configuration turns it on
+// the terms depend on every body at once, the pairwise form is the closed two-body correction
+// test particles don't feel it
+pub struct PostNewtonian;
+
+impl PairForce for PostNewtonian {
+    fn acceleration(&self, on: &BodyState, from: &BodyState, config: &Configuration) -> DVec3 {
+        if !config.post_newtonian || !relativistic(config) {
+            return DVec3::ZERO;
+        }
+        post_newtonian_pair(
+            minimum_image(on.position - from.position, config.period()),
+            (on.velocity, from.velocity),
+            (on.body.mass, from.body.mass),
+            config.gravity_constant,
+            config.speed_of_light,
+        )
+    }
+
+    fn accelerations(
+        &self,
+        buffer: &BodyBuffer,
+        bodies: &[BodyState],
+        config: &Configuration,
+    ) -> Vec<DVec3> {
+        if !config.post_newtonian || !relativistic(config) {
+            return vec![DVec3::ZERO; bodies.len()];
+        }
+        let velocities: Vec<DVec3> = bodies.iter().map(|body| body.velocity).collect();
+        // the corrections depend on the Newtonian gravity alone, not on any other force
+        let newtonian =
+            gravity_accelerations(buffer, config.gravity_constant, config.force_threads);
+        post_newtonian_accelerations(
+            buffer,
+            &velocities,
+            &newtonian,
+            config.gravity_constant,
+            config.speed_of_light,
+            config.force_threads,
+        )
+    }
+
+    fn field_accelerations(
+        &self,
+        _buffer: &BodyBuffer,
+        _bodies: &[BodyState],
+        particles: &[BodyState],
+        _config: &Configuration,
+    ) -> Vec<DVec3> {
+        vec![DVec3::ZERO; particles.len()]
+    }
+
+    // the corrected motion conserves a velocity dependent energy, which isn't tracked
+    fn potential_energy(
+        &self,
+        _a: &BodyState,
+        _b: &BodyState,
+        config: &Configuration,
+    ) -> Option<f64> {
+        (!config.post_newtonian || !relativistic(config)).then_some(0.)
+    }
+}
+
+// the 2.5 post-Newtonian radiation reaction, while the configuration turns it on, so close pairs inspiral
+// test particles radiate nothing
+pub struct RadiationReaction;
+
+impl PairForce for RadiationReaction {
+    fn acceleration(&self, on: &BodyState, from: &BodyState, config: &Configuration) -> DVec3 {
+        if !config.radiation_reaction || !relativistic(config) {
+            return DVec3::ZERO;
+        }
+        radiation_reaction_pair(
+            minimum_image(on.position - from.position, config.period()),
+            on.velocity - from.velocity,
+            (on.body.mass, from.body.mass),
+            config.gravity_constant,
+            config.speed_of_light,
+        )
+    }
+
+    fn accelerations(
+        &self,
+        buffer: &BodyBuffer,
+        bodies: &[BodyState],
+        config: &Configuration,
+    ) -> Vec<DVec3> {
+        if !config.radiation_reaction || !relativistic(config) {
+            return vec![DVec3::ZERO; bodies.len()];
+        }
+        let velocities: Vec<DVec3> = bodies.iter().map(|body| body.velocity).collect();
+        radiation_reaction_accelerations(
+            buffer,
+            &velocities,
+            config.gravity_constant,
+            config.speed_of_light,
+            config.force_threads,
+        )
+    }
+
+    fn field_accelerations(
+        &self,
+        _buffer: &BodyBuffer,
+        _bodies: &[BodyState],
+        particles: &[BodyState],
+        _config: &Configuration,
+    ) -> Vec<DVec3> {
+        vec![DVec3::ZERO; particles.len()]
+    }
+
+    // radiating is dissipative, so there is no energy to show while it is on
+    fn potential_energy(
+        &self,
+        _a: &BodyState,
+        _b: &BodyState,
+        config: &Configuration,
+    ) -> Option<f64> {
+        (!config.radiation_reaction || !relativistic(config)).then_some(0.)
+    }
+}
+
+// electrostatic attraction and repulsion between charged bodies
+pub struct Coulomb;
+
+impl PairForce for Coulomb {
+    fn acceleration(&self, on: &BodyState, from: &BodyState, config: &Configuration) -> DVec3 {
+        let separation = minimum_image(on.position - from.position, config.period());
+        let r = separation.length();
+        // massless test particles carry no charge
+        if r == 0. || on.body.mass == 0. {
+            return DVec3::ZERO;
+        }
+        config.coulomb_constant * on.charge * from.charge * separation / (r.powi(3) * on.body.mass)
+    }
+
+    fn accelerations(
+        &self,
+        _buffer: &BodyBuffer,
+        bodies: &[BodyState],
+        config: &Configuration,
+    ) -> Vec<DVec3> {
+        // nothing to do until something is charged
+        if bodies.iter().all(|body| body.charge == 0.) {
+            return vec![DVec3::ZERO; bodies.len()];
+        }
+        pairwise_accelerations(self, bodies, config)
+    }
+
+    fn field_accelerations(
+        &self,
+        _buffer: &BodyBuffer,
+        _bodies: &[BodyState],
+        particles: &[BodyState],
+        _config: &Configuration,
+    ) -> Vec<DVec3> {
+        vec![DVec3::ZERO; particles.len()]
+    }
+
+    fn potential_energy(
+        &self,
+        a: &BodyState,
+        b: &BodyState,
+        config: &Configuration,
+    ) -> Option<f64> {
+        let r = minimum_image(b.position - a.position, config.period()).length();
+        if r == 0. || a.body.mass == 0. || b.body.mass == 0. {
+            return Some(0.);
+        }
+        Some(config.coulomb_constant * a.charge * b.charge / r)
+    }
+}
+
+// the background fields in the configuration's external_potentials
+pub struct ExternalFields;
+
+impl BodyForce for ExternalFields {
+    fn acceleration(&self, body: &BodyState, config: &Configuration) -> DVec3 {
+        external_acceleration(
+            &config.external_potentials,
+            body.position,
+            config.gravity_constant,
+        )
+    }
+
+    fn potential_energy(&self, body: &BodyState, config: &Configuration) -> Option<f64> {
+        Some(
+            body.body.mass
+                * external_potential(
+                    &config.external_potentials,
+                    body.position,
+                    config.gravity_constant,
+                ),
+        )
+    }
+}
+
+// kinetic energy, spin included, plus the potential energy of every pair and of every body in the fields,
+// None if any registered law has no potential energy
+pub fn mechanical_energy(
+    laws: &ForceLaws,
+    bodies: &[BodyState],
+    config: &Configuration,
+) -> Option<f64> {
+    let kinetic: f64 = bodies
+        .iter()
+        .map(|state| {
+            0.5 * state.body.mass * state.velocity.length_squared()
+                + 0.5 * moment_of_inertia(state.body) * state.spin.length_squared()
+        })
+        .sum();
+    let pairs = par_map_bodies(bodies.len(), config.force_threads, |i| {
+        bodies[i + 1..]
+            .iter()
+            .map(|other| {
+                laws.pair
+                    .iter()
+                    .map(|law| law.potential_energy(&bodies[i], other, config))
+                    .sum::<Option<f64>>()
+            })
+            .sum::<Option<f64>>()
+    })
+    .into_iter()
+    .sum::<Option<f64>>()?;
+    let fields = bodies
+        .iter()
+        .flat_map(|state| {
+            laws.body
+                .iter()
+                .map(|law| law.potential_energy(state, config))
+        })
+        .sum::<Option<f64>>()?;
+    Some(kinetic + pairs + fields)
+}
+
+// registering force laws with the app, the laws are summed in the order they are added
+pub trait AddForces {
+    fn add_pair_force(&mut self, force: impl PairForce) -> &mut Self;
+    fn add_body_force(&mut self, force: impl BodyForce) -> &mut Self;
+}
+
+impl AddForces for App {
+    fn add_pair_force(&mut self, force: impl PairForce) -> &mut Self {
+        self.world_mut()
+            .get_resource_or_insert_with(ForceLaws::default)
+            .pair
+            .push(Box::new(force));
+        self
+    }
+
+    fn add_body_force(&mut self, force: impl BodyForce) -> &mut Self {
+        self.world_mut()
+            .get_resource_or_insert_with(ForceLaws::default)
+            .body
+            .push(Box::new(force));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExternalPotential;
+    use bevy::tasks::{ComputeTaskPool, TaskPool};
+
+    fn state(mass: f64, position: DVec3, velocity: DVec3, charge: f64) -> BodyState {
+        BodyState {
+            body: Body { mass, density: 1. },
+            position,
+            velocity,
+            spin: DVec3::ZERO,
+            charge,
+        }
+    }
+
+    fn pair() -> (BodyState, BodyState) {
+        (
+            state(
+                2.,
+                DVec3::new(0.3, -0.2, 0.5),
+                DVec3::new(0.1, 1.2, -0.3),
+                1.5,
+            ),
+            state(
+                3.,
+                DVec3::new(1.7, 0.4, -0.6),
+                DVec3::new(-0.4, -0.8, 0.2),
+                -0.7,
+            ),
+        )
+    }
+
+    // minus the gradient of f at p, by central differences
+    fn downhill(f: impl Fn(DVec3) -> f64, p: DVec3) -> DVec3 {
+        let h = 1e-6;
+        let d = |axis: DVec3| (f(p + h * axis) - f(p - h * axis)) / (2. * h);
+        -DVec3::new(d(DVec3::X), d(DVec3::Y), d(DVec3::Z))
+    }
+
+    fn assert_close(a: DVec3, b: DVec3) {
+        assert!(
+            (a - b).length() <= 1e-6 * b.length().max(1e-12),
+            "{a} vs {b}"
+        );
+    }
+
+    // each pair law's acceleration is the force from its potential energy, divided by the mass
+    #[test]
+    fn pair_potentials_match_the_accelerations() {
+        let (a, b) = pair();
+        let mut config = Configuration::default();
+        let check = |law: &dyn PairForce, config: &Configuration| {
+            let energy = |position| {
+                let moved = BodyState { position, ..a };
+                law.potential_energy(&moved, &b, config).unwrap()
+            };
+            assert_close(
+                law.acceleration(&a, &b, config),
+                downhill(energy, a.position) / a.body.mass,
+            );
+        };
+        for gravity_law in [
+            GravityLaw::Newtonian,
+            GravityLaw::PowerLaw,
+            GravityLaw::Yukawa,
+        ] {
+            config.gravity_law = gravity_law;
+            check(&Gravity, &config);
+        }
+        config.gravity_law = GravityLaw::PowerLaw;
+        config.gravity_power = 1.;
+        check(&Gravity, &config);
+        check(&Coulomb, &config);
+    }
+
+    #[test]
+    fn field_potentials_match_the_accelerations() {
+        let body = state(2., DVec3::new(0.3, -0.2, 0.5), DVec3::ZERO, 0.);
+        let center = [0.1, 0.2, -0.1];
+        let potentials = [
+            ExternalPotential::Uniform {
+                acceleration: [0.1, -0.2, 0.3],
+            },
+            ExternalPotential::PointMass { mass: 5., center },
+            ExternalPotential::LogarithmicHalo {
+                velocity: 1.5,
+                core_radius: 0.4,
+                center,
+            },
+            ExternalPotential::NfwHalo {
+                mass: 5.,
+                scale_radius: 2.,
+                center,
+            },
+            ExternalPotential::MiyamotoNagai {
+                mass: 5.,
+                radial_scale: 1.,
+                vertical_scale: 0.2,
+                center,
+            },
+            ExternalPotential::HarmonicTrap {
+                frequency: 0.7,
+                center,
+            },
+        ];
+        for potential in potentials {
+            let config = Configuration {
+                external_potentials: vec![potential],
+                ..default()
+            };
+            let energy = |position| {
+                let moved = BodyState { position, ..body };
+                ExternalFields.potential_energy(&moved, &config).unwrap()
+            };
+            assert_close(
+                ExternalFields.acceleration(&body, &config),
+                downhill(energy, body.position) / body.body.mass,
+            );
+        }
+    }
+
+    #[test]
+    fn laws_without_a_potential_say_so() {
+        let (a, b) = pair();
+        let mut config = Configuration::default();
+        assert_eq!(PostNewtonian.potential_energy(&a, &b, &config), Some(0.));
+        assert_eq!(
+            RadiationReaction.potential_energy(&a, &b, &config),
+            Some(0.)
+        );
+        config.post_newtonian = true;
+        config.radiation_reaction = true;
+        assert_eq!(PostNewtonian.potential_energy(&a, &b, &config), None);
+        assert_eq!(RadiationReaction.potential_energy(&a, &b, &config), None);
+        config.gravity_law = GravityLaw::Mond;
+        assert_eq!(Gravity.potential_energy(&a, &b, &config), None);
+    }
+
+    // the pairwise relativistic terms are the whole-system ones for the two bodies alone
+    #[test]
+    fn relativistic_pairs_match_the_system() {
+        ComputeTaskPool::get_or_init(TaskPool::default);
+        let (a, b) = pair();
+        let config = Configuration {
+            post_newtonian: true,
+            radiation_reaction: true,
+            ..default()
+        };
+        let states = [a, b];
+        let mut buffer = BodyBuffer::default();
+        buffer.gather(states.iter().map(|s| (s.position, s.body.mass)));
+        for law in [&PostNewtonian as &dyn PairForce, &RadiationReaction] {
+            let system = law.accelerations(&buffer, &states, &config);
+            let tolerance = 1e-12 * system[0].length();
+            assert!((law.acceleration(&a, &b, &config) - system[0]).length() <= tolerance);
+            assert!((law.acceleration(&b, &a, &config) - system[1]).length() <= tolerance);
+        }
+    }
+}
@@ -1,6 +1,15 @@
-use crate::components::{Body, Position, Velocity};
-use crate::resources::SphereInfo;
+use std::{
+    f64::consts::{PI, TAU},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::components::{
+    Body, Charge, Orientation, Position, PreviousPosition, Spin, TestParticle, Velocity,
+};
+use crate::resources::{BodyState, SpawnPattern, SphereInfo};
 use bevy::{
+    math::{DQuat, DVec3},
     prelude::*,
     render::{
         render_asset::RenderAssetUsages,
@@ -8,12 +17,93 @@ use bevy::{
     },
 };
 
-pub fn get_radius(body: Body) -> f32 {
-    body.mass.cbrt() * get_default_sphere_radius()
+pub fn get_radius(body: Body) -> f64 {
+    (body.mass / body.density).cbrt() * get_default_sphere_radius() as f64
+}
+
+pub fn get_mass(radius: f64, density: f64) -> f64 {
+    (radius / get_default_sphere_radius() as f64).powf(3.) * density
+}
+
+// mass per unit volume of a body with the given relative density
+pub fn volume_density(density: f64) -> f64 {
+    get_mass(1., density) / (4. / 3. * PI)
+}
+
+// moment of inertia of a uniform sphere
+pub fn moment_of_inertia(body: Body) -> f64 {
+    0.4 * body.mass * get_radius(body).powi(2)
+}
+
+// volumes add when bodies merge, so the merged density is the total mass over the total volume
+pub fn merged_density(body1: Body, body2: Body) -> f64 {
+    (body1.mass + body2.mass) / (body1.mass / body1.density + body2.mass / body2.density)
+}
+
+// distance from a primary inside which a fluid satellite is pulled apart by tides, which only depends on
+// the primary's radius and the ratio of the densities
+pub fn roche_limit(primary: Body, satellite: Body) -> f64 {
+    2.44 * get_radius(primary) * (primary.density / satellite.density).cbrt()
+}
+
+// the equal fragments a body torn apart by a primary breaks into, given its offset from the primary and the
+// primary's radius, or none if the body is already touching the primary and about to hit it instead
+// they keep the body's density and lie in a line along its orbit, just clear of each other, moving with the body
+// and sharing its spin, so mass, momentum, angular momentum and charge are kept and the line is never nearer
+// the primary than the body was
+pub fn tidal_fragments(
+    state: BodyState,
+    offset: DVec3,
+    relative_velocity: DVec3,
+    primary_radius: f64,
+    fragments: usize,
+) -> Option<Vec<BodyState>> {
+    let fragment = Body {
+        mass: state.body.mass / fragments as f64,
+        density: state.body.density,
+    };
+    let radius = get_radius(fragment);
+    if offset.length() < primary_radius + radius {
+        return None;
+    }
+    let radial = offset.normalize();
+    // along the orbit, or any way across the line to the primary when falling straight in
+    let along = relative_velocity
+        .reject_from_normalized(radial)
+        .try_normalize()
+        .unwrap_or_else(|| radial.any_orthonormal_vector());
+    // spaced so they don't merge straight back together
+    let spacing = 2.02 * radius;
+    let spin = moment_of_inertia(state.body) * state.spin
+        / (fragments as f64 * moment_of_inertia(fragment));
+    Some(
+        (0..fragments)
+            .map(|k| BodyState {
+                body: fragment,
+                position: state.position
+                    + along * spacing * (k as f64 - (fragments - 1) as f64 / 2.),
+                velocity: state.velocity,
+                spin,
+                charge: state.charge / fragments as f64,
+            })
+            .collect(),
+    )
 }
 
-pub fn get_mass(radius: f32) -> f32 {
-    (radius / get_default_sphere_radius()).powf(3.)
+// a file next to the executable, like config.json
+pub fn executable_dir_file(name: &str) -> Option<PathBuf> {
+    if let Ok(path) = std::env::current_exe() {
+        match path.parent() {
+            Some(parent_path) => Some(parent_path.to_path_buf().join(name)),
+            None => {
+                println!("Could not open the executable's parent directory, not using {name}.");
+                None
+            }
+        }
+    } else {
+        println!("Could not get the executable's directory, not using {name}.");
+        None
+    }
 }
 
 pub fn get_default_sphere_radius() -> f32 {
@@ -21,9 +111,10 @@ pub fn get_default_sphere_radius() -> f32 {
 }
 
 pub fn body_bundle(
-    mass: f32,
-    position: Vec3,
-    velocity: Vec3,
+    body: Body,
+    position: DVec3,
+    velocity: DVec3,
+    spin: DVec3,
     sphere_info: &Res<SphereInfo>,
 ) -> impl Bundle {
     // get or add the mesh handle
@@ -32,9 +123,12 @@ pub fn body_bundle(
     // get or add the material handle
     let material_handle = sphere_info.1.clone();
     (
-        Body { mass },
+        body,
         Position(position),
+        PreviousPosition(position),
         Velocity(velocity),
+        Spin(spin),
+        Orientation(DQuat::IDENTITY),
         PbrBundle {
             mesh: mesh_handle,
             material: material_handle,
@@ -43,6 +137,154 @@ pub fn body_bundle(
     )
 }
 
+pub fn spawn_body(
+    commands: &mut Commands,
+    state: BodyState,
+    sphere_info: &Res<SphereInfo>,
+) -> Entity {
+    let mut entity = commands.spawn(body_bundle(
+        state.body,
+        state.position,
+        state.velocity,
+        state.spin,
+        sphere_info,
+    ));
+    if state.body.mass == 0. {
+        entity.insert(TestParticle);
+    }
+    if state.charge != 0. {
+        entity.insert(Charge(state.charge));
+    }
+    entity.id()
+}
+
+// position and velocity of the center of mass, and the total mass, of (mass, position, velocity) triples
+pub fn center_of_mass(bodies: impl Iterator<Item = (f64, DVec3, DVec3)>) -> (DVec3, DVec3, f64) {
+    let (weighted_position, weighted_velocity, total_mass) = bodies.fold(
+        (DVec3::ZERO, DVec3::ZERO, 0.),
+        |(p, v, m), (mass, position, velocity)| {
+            (p + mass * position, v + mass * velocity, m + mass)
+        },
+    );
+    if total_mass == 0. {
+        return (DVec3::ZERO, DVec3::ZERO, 0.);
+    }
+    (
+        weighted_position / total_mass,
+        weighted_velocity / total_mass,
+        total_mass,
+    )
+}
+
+// velocity for a body at position so that it is at the periapsis of an orbit around a target with the given eccentricity
+// circular_speed is the speed of a circular orbit there, 0 eccentricity is circular and, for inverse square gravity,
+// 1 is exactly escape speed
+// the orbit lies in the plane of the offset and the aim direction, falling back to any perpendicular if they are parallel
+pub fn orbit_velocity(
+    position: DVec3,
+    aim: DVec3,
+    target_position: DVec3,
+    target_velocity: DVec3,
+    circular_speed: f64,
+    eccentricity: f64,
+) -> DVec3 {
+    let radial = (position - target_position).normalize();
+    let mut tangent = aim.reject_from_normalized(radial).normalize_or_zero();
+    if tangent == DVec3::ZERO {
+        tangent = radial.any_orthonormal_vector();
+    }
+    target_velocity + tangent * circular_speed * (1. + eccentricity).sqrt()
+}
+
+// small splitmix64 generator for spawn patterns, seeded from the clock
+pub struct Rng(u64);
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64),
+        )
+    }
+}
+
+impl Rng {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // uniform inside the unit ball
+    pub fn in_unit_ball(&mut self) -> DVec3 {
+        loop {
+            let point =
+                DVec3::new(self.next_f64(), self.next_f64(), self.next_f64()) * 2. - DVec3::ONE;
+            if point.length_squared() <= 1. {
+                return point;
+            }
+        }
+    }
+
+    // uniform on the unit sphere
+    pub fn on_unit_sphere(&mut self) -> DVec3 {
+        let z = 2. * self.next_f64() - 1.;
+        let (sin, cos) = (TAU * self.next_f64()).sin_cos();
+        let r = (1. - z * z).sqrt();
+        DVec3::new(r * cos, r * sin, z)
+    }
+}
+
+// offsets from the spawn point for each body of a shot
+// forward and right are the camera's directions, lines run forward and rings lie in their plane
+pub fn pattern_offsets(
+    pattern: SpawnPattern,
+    count: usize,
+    spread: f64,
+    forward: DVec3,
+    right: DVec3,
+    rng: &mut Rng,
+) -> Vec<DVec3> {
+    (0..count)
+        .map(|k| match pattern {
+            SpawnPattern::Line if count > 1 => forward * (spread * k as f64 / (count - 1) as f64),
+            SpawnPattern::Line => DVec3::ZERO,
+            SpawnPattern::Cluster => rng.in_unit_ball() * spread,
+            SpawnPattern::Shell => rng.on_unit_sphere() * spread,
+            SpawnPattern::Ring => {
+                let (sin, cos) = (TAU * k as f64 / count as f64).sin_cos();
+                (right * cos + forward * sin) * spread
+            }
+        })
+        .collect()
+}
+
+// distance along a ray (with normalized direction) to where it enters a sphere, if it hits it
+pub fn ray_sphere_distance(
+    origin: DVec3,
+    direction: DVec3,
+    center: DVec3,
+    radius: f64,
+) -> Option<f64> {
+    let offset = center - origin;
+    let closest = offset.dot(direction);
+    let miss2 = offset.length_squared() - closest * closest;
+    if miss2 > radius * radius {
+        return None;
+    }
+    let half_chord = (radius * radius - miss2).sqrt();
+    // the sphere is behind the ray if its far side is, and the ray may start inside it
+    (closest + half_chord >= 0.).then_some((closest - half_chord).max(0.))
+}
+
 // from 3d_shapes.rs bevy example
 pub fn uv_debug_texture() -> Image {
     const TEXTURE_SIZE: usize = 8;
@@ -71,3 +313,70 @@ pub fn uv_debug_texture() -> Image {
         RenderAssetUsages::RENDER_WORLD,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn angular_momentum(states: &[BodyState]) -> DVec3 {
+        states
+            .iter()
+            .map(|s| {
+                s.body.mass * s.position.cross(s.velocity) + moment_of_inertia(s.body) * s.spin
+            })
+            .sum()
+    }
+
+    #[test]
+    fn tidal_fragments_conserve_and_clear_the_primary() {
+        let primary = Body {
+            mass: 100.,
+            density: 1.,
+        };
+        let primary_radius = get_radius(primary);
+        let body = BodyState {
+            body: Body {
+                mass: 2.,
+                density: 1.,
+            },
+            position: DVec3::new(primary_radius + 0.6, 0.2, -0.1),
+            velocity: DVec3::new(0.3, 4., 0.5),
+            spin: DVec3::new(0.1, -0.2, 0.7),
+            charge: 0.8,
+        };
+        let offset = body.position;
+        for (relative_velocity, fragments) in [(body.velocity, 8), (-offset, 5)] {
+            let stream =
+                tidal_fragments(body, offset, relative_velocity, primary_radius, fragments)
+                    .unwrap();
+            assert_eq!(stream.len(), fragments);
+            let mass: f64 = stream.iter().map(|s| s.body.mass).sum();
+            let momentum: DVec3 = stream.iter().map(|s| s.body.mass * s.velocity).sum();
+            let charge: f64 = stream.iter().map(|s| s.charge).sum();
+            assert!((mass - body.body.mass).abs() < 1e-12);
+            assert!((momentum - body.body.mass * body.velocity).length() < 1e-12);
+            assert!((charge - body.charge).abs() < 1e-12);
+            assert!(
+                (angular_momentum(&stream) - angular_momentum(&[body])).length() < 1e-12,
+                "{0} vs {1}",
+                angular_momentum(&stream),
+                angular_momentum(&[body])
+            );
+            for (i, a) in stream.iter().enumerate() {
+                let radius = get_radius(a.body);
+                assert!(a.position.length() >= primary_radius + radius);
+                for b in &stream[i + 1..] {
+                    assert!(a.position.distance(b.position) > 2. * radius);
+                }
+            }
+        }
+        // a body already touching the primary hits it instead
+        let grazing = BodyState {
+            position: DVec3::X * primary_radius,
+            ..body
+        };
+        assert!(
+            tidal_fragments(grazing, grazing.position, body.velocity, primary_radius, 8).is_none()
+        );
+    }
+}
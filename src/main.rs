@@ -1,38 +1,35 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
-use std::{fs::File, io::Read, path::PathBuf};
+use std::{fs::File, io::Read};
 
 use bevy::{prelude::*, window::Cursor};
 
+mod collision;
 mod components;
 mod config;
+mod events;
+mod forces;
 mod helpers;
+mod physics;
 mod resources;
 mod systems;
+mod units;
 
 use config::Configuration;
+use events::{CollisionEvent, EscapeEvent};
+use forces::{AddForces, Coulomb, ExternalFields, Gravity, PostNewtonian, RadiationReaction};
+use helpers::executable_dir_file;
 use resources::*;
 use systems::*;
 
 // TODO(henrygerardmoore): test on macOS
 // TODO(henrygerardmoore): allow bodies to be spawned from a config file
 fn main() {
-    let config_path: Option<PathBuf> = if let Ok(path) = std::env::current_exe() {
-        match path.parent() {
-            Some(parent_path) => Some(parent_path.to_path_buf().join("config.json")),
-            None => {
-                println!("Could not open the executable's parent directory, using default configuration.");
-                None
-            }
-        }
-    } else {
-        println!("Could not get the executable's directory, using default configuration.");
-        None
-    };
-    let config = if config_path.is_some() {
+    let config_path = executable_dir_file("config.json");
+    let mut config = if config_path.is_some() {
         if let Ok(mut f) = File::open(config_path.unwrap()) {
             let mut data = String::new();
             if f.read_to_string(&mut data).is_ok() {
-                match serde_json::from_str(&data) {
+                match Configuration::from_json(&data) {
                     Ok(j) => j,
                     Err(_) => {
                         println!("Could not read your config.json into json, using default configuration");
@@ -52,6 +49,7 @@ fn main() {
     } else {
         Configuration::default()
     };
+    config.derive_constants();
 
     App::new()
         .add_plugins(
@@ -76,7 +74,32 @@ fn main() {
         // insert the common sphere that all bodies use
         .insert_resource(SphereInfo::default())
         // start the spawn selection at default
-        .insert_resource(BodySpawningOptions::default())
+        .insert_resource(BodySpawningOptions::for_units(config.units))
+        .init_resource::<Slingshot>()
+        .init_resource::<ReferenceFrame>()
+        // saved spawn option presets
+        .insert_resource(SpawnPresets::load())
+        // throughput measurements for benchmark mode
+        .init_resource::<Benchmark>()
+        // recent collisions for the on-screen log
+        .init_resource::<CollisionLog>()
+        .add_event::<CollisionEvent>()
+        .add_event::<EscapeEvent>()
+        // undo and redo of spawns, deletions and resets
+        .init_resource::<EditHistory>()
+        // forces summed into every body's acceleration, more can be registered the same way
+        .add_pair_force(Gravity)
+        .add_pair_force(PostNewtonian)
+        .add_pair_force(RadiationReaction)
+        .add_pair_force(Coulomb)
+        .add_body_force(ExternalFields)
+        // the binary watched by the gravitational wave overlay
+        .init_resource::<GravitationalWaves>()
+        // energy lost to drag
+        .init_resource::<Dissipation>()
+        // simulated time and the recent states that can be rewound to
+        .init_resource::<SimulationClock>()
+        .init_resource::<Rewind>()
         // add configuration resource for use by systems
         .insert_resource(config)
         // add startup systems
@@ -84,32 +107,90 @@ fn main() {
         .add_systems(Startup, camera_spawn)
         .add_systems(Startup, create_osd)
         .add_systems(Startup, spawn_help)
+        .add_systems(Startup, create_collision_log)
+        .add_systems(Startup, create_gravitational_wave_overlay)
+        .add_systems(Startup, create_timeline)
         // integration (must be performed in order)
         .add_systems(
             Update,
             (
-                update_body_velocities,
+                advance_clock,
                 update_body_positions,
+                update_body_orientations,
+                apply_boundary,
+                update_body_velocities,
+                update_particle_velocities,
+                apply_drag,
                 resolve_body_collisions,
-                update_body_meshes,
+                tidal_disruption,
+                record_snapshot,
+                record_strain,
             )
                 .chain(),
         )
+        // rewinding replaces the bodies, so it happens before they move
+        .add_systems(Update, scrub_timeline.before(advance_clock))
+        .add_systems(Update, update_timeline.after(record_snapshot))
+        // reference frame, after both bodies and camera have moved
+        .add_systems(Update, reference_frame_selection)
+        .add_systems(
+            Update,
+            (recenter_bodies, follow_reference_frame)
+                .chain()
+                .after(record_snapshot)
+                .after(move_camera)
+                .after(rotate_camera)
+                .after(reference_frame_selection),
+        )
+        // rendering relative to the camera, once it has followed the reference frame
+        .add_systems(Update, update_body_meshes.after(follow_reference_frame))
         // resetting the world
         .add_systems(Update, reset_bodies)
         .add_systems(Update, reset_camera)
+        // optional physics
+        .add_systems(Update, toggle_physics)
+        .add_systems(Update, draw_boundary.after(follow_reference_frame))
+        // gravitational waves from the selected binary, drawn once the camera has followed the reference frame
+        .add_systems(
+            Update,
+            (update_gravitational_waves, draw_waveform)
+                .chain()
+                .after(follow_reference_frame),
+        )
         // changing time rate
-        .add_systems(Update, modify_time)
+        .add_systems(Update, modify_time.before(advance_clock))
         // spawning bodies
         .add_systems(Update, spawn_mode_selection)
         .add_systems(Update, spawn_scrolling)
+        .add_systems(Update, spawn_presets)
         .add_systems(Update, spawn)
+        .add_systems(Update, stream_spawn)
+        .add_systems(Update, select_body)
+        .add_systems(Update, pin_spawn_point)
+        .add_systems(
+            Update,
+            (toggle_slingshot, slingshot, draw_slingshot).chain(),
+        )
+        .add_systems(Update, delete_body)
+        // undoing and redoing world edits
+        .add_systems(Update, undo_redo)
         // camera
         .add_systems(Update, rotate_camera)
         .add_systems(Update, move_camera)
         // UI
         .add_systems(Update, update_osd)
         .add_systems(Update, show_hide_help)
+        // collision reporting
+        .add_systems(
+            Update,
+            (
+                update_collision_log,
+                spawn_impact_flashes,
+                write_collision_events,
+            )
+                .after(resolve_body_collisions),
+        )
+        .add_systems(Update, animate_impact_flashes.after(follow_reference_frame))
         // general
         .add_systems(Update, capture_or_release_cursor)
         .add_systems(Update, exit_system)
@@ -0,0 +1,873 @@
+use bevy::{math::DVec3, tasks::ComputeTaskPool};
+
+use crate::{
+    components::Body,
+    config::{Configuration, Drag, ExternalPotential},
+    helpers::get_radius,
+};
+
+// number of bodies the kernel handles at once, four f64s fill a 256 bit vector register
+pub const LANES: usize = 4;
+
+// bodies gathered out of the ECS into a structure of arrays each step
+// the arrays are padded with massless bodies up to a multiple of LANES so the kernel never needs a remainder loop
+#[derive(Default)]
+pub struct BodyBuffer {
+    pub x: Vec<f64>,
+    pub y: Vec<f64>,
+    pub z: Vec<f64>,
+    pub mass: Vec<f64>,
+    len: usize,
+    // side length of the periodic box, if space wraps around, so each body only pulls through its nearest image
+    pub period: Option<f64>,
+}
+
+impl BodyBuffer {
+    // refill the buffer, reusing its allocations
+    pub fn gather(&mut self, bodies: impl Iterator<Item = (DVec3, f64)>) {
+        self.x.clear();
+        self.y.clear();
+        self.z.clear();
+        self.mass.clear();
+        for (position, mass) in bodies {
+            self.x.push(position.x);
+            self.y.push(position.y);
+            self.z.push(position.z);
+            self.mass.push(mass);
+        }
+        self.len = self.mass.len();
+        let padded = self.len.next_multiple_of(LANES);
+        self.x.resize(padded, 0.);
+        self.y.resize(padded, 0.);
+        self.z.resize(padded, 0.);
+        self.mass.resize(padded, 0.);
+    }
+
+    // number of real (non-padding) bodies
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn position(&self, i: usize) -> DVec3 {
+        DVec3::new(self.x[i], self.y[i], self.z[i])
+    }
+
+    // offset of body a from the nearest image of body b
+    pub fn separation(&self, a: usize, b: usize) -> DVec3 {
+        minimum_image(self.position(a) - self.position(b), self.period)
+    }
+}
+
+// the shortest of the offsets between images of two points, in a periodic box with the given side
+pub fn minimum_image(offset: DVec3, period: Option<f64>) -> DVec3 {
+    match period {
+        Some(period) => offset - period * (offset / period).round(),
+        None => offset,
+    }
+}
+
+// fold a position that went past the walls of a reflecting box, from -size to size on each axis, back inside
+// reflections repeat every two box widths and an odd number of them reverses the velocity
+// the previous position is mirrored the same way, so the step's path is the mirror image of the one that left
+pub fn reflect_into_box(
+    position: &mut DVec3,
+    previous: &mut DVec3,
+    velocity: &mut DVec3,
+    size: f64,
+) {
+    for axis in 0..3 {
+        if position[axis].abs() <= size {
+            continue;
+        }
+        let turns = ((position[axis] + size) / (4. * size)).floor();
+        let folded = position[axis] + size - 4. * size * turns;
+        if folded > 2. * size {
+            let mirror = 2. * size + 4. * size * turns;
+            position[axis] = mirror - position[axis];
+            previous[axis] = mirror - previous[axis];
+            velocity[axis] = -velocity[axis];
+        } else {
+            position[axis] -= 4. * size * turns;
+            previous[axis] -= 4. * size * turns;
+        }
+    }
+}
+
+// move a position that left a periodic box, from -size to size on each axis, to its image inside
+// the previous position moves along too so the body isn't swept across the whole box
+pub fn wrap_into_box(position: &mut DVec3, previous: &mut DVec3, size: f64) {
+    let shift = (*position / (2. * size)).round() * 2. * size;
+    *position -= shift;
+    *previous -= shift;
+}
+
+// gravitational acceleration at point p from every body
+// the inner loop works on LANES bodies at a time with independent accumulators so it compiles to vector instructions
+// a body at zero distance (itself, or padding on top of it) contributes nothing, masked out arithmetically
+// since a branch there keeps the loop from vectorizing
+// the periodic version is separate so the wrapping doesn't slow down the usual case
+fn gravity_acceleration_at<const PERIODIC: bool>(
+    p: DVec3,
+    bodies: &BodyBuffer,
+    gravity_constant: f64,
+) -> DVec3 {
+    let period = bodies.period.unwrap_or(0.);
+    let inverse_period = if PERIODIC { 1. / period } else { 0. };
+    let mut ax = [0.; LANES];
+    let mut ay = [0.; LANES];
+    let mut az = [0.; LANES];
+    let chunks = bodies
+        .x
+        .chunks_exact(LANES)
+        .zip(bodies.y.chunks_exact(LANES))
+        .zip(bodies.z.chunks_exact(LANES))
+        .zip(bodies.mass.chunks_exact(LANES));
+    for (((x, y), z), m) in chunks {
+        for l in 0..LANES {
+            let mut dx = x[l] - p.x;
+            let mut dy = y[l] - p.y;
+            let mut dz = z[l] - p.z;
+            if PERIODIC {
+                dx -= period * (dx * inverse_period).round();
+                dy -= period * (dy * inverse_period).round();
+                dz -= period * (dz * inverse_period).round();
+            }
+            let dist2 = dx * dx + dy * dy + dz * dz;
+            let mask = f64::from(u8::from(dist2 > 0.));
+            let safe = dist2 + (1. - mask);
+            let factor = mask * m[l] / (safe * safe.sqrt());
+            ax[l] += dx * factor;
+            ay[l] += dy * factor;
+            az[l] += dz * factor;
+        }
+    }
+    gravity_constant * DVec3::new(ax.iter().sum(), ay.iter().sum(), az.iter().sum())
+}
+
+// evaluate f for every body index 0..n, split across the compute task pool, with results in index order
+// threads is the number of chunks to split the bodies into, 0 uses one chunk per pool thread
+pub fn par_map_bodies<T: Send + 'static>(
+    n: usize,
+    threads: usize,
+    f: impl Fn(usize) -> T + Sync,
+) -> Vec<T> {
+    if n == 0 {
+        return Vec::new();
+    }
+    let pool = ComputeTaskPool::get();
+    let threads = if threads == 0 {
+        pool.thread_num()
+    } else {
+        threads
+    };
+    let chunk_size = n.div_ceil(threads.max(1));
+    let f = &f;
+    pool.scope(|s| {
+        for start in (0..n).step_by(chunk_size) {
+            let end = (start + chunk_size).min(n);
+            s.spawn(async move { (start..end).map(f).collect::<Vec<T>>() });
+        }
+    })
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+// compute the gravitational acceleration of every body, split across the compute task pool
+// every body is evaluated independently so the output is bit-identical for any number of threads
+pub fn gravity_accelerations(
+    bodies: &BodyBuffer,
+    gravity_constant: f64,
+    threads: usize,
+) -> Vec<DVec3> {
+    par_map_bodies(bodies.len(), threads, |i| {
+        if bodies.period.is_some() {
+            gravity_acceleration_at::<true>(bodies.position(i), bodies, gravity_constant)
+        } else {
+            gravity_acceleration_at::<false>(bodies.position(i), bodies, gravity_constant)
+        }
+    })
+}
+
+// gravitational acceleration of the bodies at each of the given points, which feel gravity but don't exert it
+// this costs the number of bodies times the number of points, rather than the square of both together
+pub fn field_accelerations(
+    bodies: &BodyBuffer,
+    points: &[DVec3],
+    gravity_constant: f64,
+    threads: usize,
+) -> Vec<DVec3> {
+    par_map_bodies(points.len(), threads, |i| {
+        if bodies.period.is_some() {
+            gravity_acceleration_at::<true>(points[i], bodies, gravity_constant)
+        } else {
+            gravity_acceleration_at::<false>(points[i], bodies, gravity_constant)
+        }
+    })
+}
+
+// gravitational potential (G times the sum of m / r over the other bodies) at body i
+fn gravity_potential_at(i: usize, bodies: &BodyBuffer, gravity_constant: f64) -> f64 {
+    let mut potential = 0.;
+    for j in 0..bodies.len() {
+        let distance = bodies.separation(i, j).length();
+        if i != j && distance > 0. {
+            potential += bodies.mass[j] / distance;
+        }
+    }
+    gravity_constant * potential
+}
+
+// first post-Newtonian correction to each body's acceleration, from the Einstein-Infeld-Hoffmann equations
+// newtonian holds the Newtonian accelerations, which the correction depends on
+pub fn post_newtonian_accelerations(
+    bodies: &BodyBuffer,
+    velocities: &[DVec3],
+    newtonian: &[DVec3],
+    gravity_constant: f64,
+    speed_of_light: f64,
+    threads: usize,
+) -> Vec<DVec3> {
+    let n = bodies.len();
+    let potentials = par_map_bodies(n, threads, |i| {
+        gravity_potential_at(i, bodies, gravity_constant)
+    });
+    let c2 = speed_of_light * speed_of_light;
+    par_map_bodies(n, threads, |a| {
+        let v_a = velocities[a];
+        let mut acceleration = DVec3::ZERO;
+        for b in 0..n {
+            if a == b {
+                continue;
+            }
+            let separation = bodies.separation(a, b);
+            if separation == DVec3::ZERO {
+                continue;
+            }
+            acceleration += eih_term(
+                separation,
+                (v_a, velocities[b]),
+                gravity_constant * bodies.mass[b],
+                (potentials[a], potentials[b]),
+                newtonian[b],
+            );
+        }
+        acceleration / c2
+    })
+}
+
+// body b's term in the first post-Newtonian acceleration of body a, times c^2
+// separation points from b to a, and the potentials and b's Newtonian acceleration are those of the whole system
+fn eih_term(
+    separation: DVec3,
+    (v_a, v_b): (DVec3, DVec3),
+    gm_b: f64,
+    (potential_a, potential_b): (f64, f64),
+    newtonian_b: DVec3,
+) -> DVec3 {
+    let r = separation.length();
+    // unit vector from b to a
+    let n_ab = separation / r;
+    let gm_r2 = gm_b / (r * r);
+    let bracket = v_a.length_squared() + 2. * v_b.length_squared()
+        - 4. * v_a.dot(v_b)
+        - 1.5 * n_ab.dot(v_b).powi(2)
+        - 4. * potential_a
+        - potential_b
+        - 0.5 * separation.dot(newtonian_b);
+    -n_ab * gm_r2 * bracket
+        + gm_r2 * n_ab.dot(4. * v_a - 3. * v_b) * (v_a - v_b)
+        + 3.5 * gm_b / r * newtonian_b
+}
+
+// the first post-Newtonian correction to body a's acceleration in an isolated pair with body b
+// separation points from b to a
+pub fn post_newtonian_pair(
+    separation: DVec3,
+    velocities: (DVec3, DVec3),
+    (mass_a, mass_b): (f64, f64),
+    gravity_constant: f64,
+    speed_of_light: f64,
+) -> DVec3 {
+    let r = separation.length();
+    if r == 0. {
+        return DVec3::ZERO;
+    }
+    let potentials = (gravity_constant * mass_b / r, gravity_constant * mass_a / r);
+    let newtonian_b = gravity_constant * mass_a * separation / r.powi(3);
+    eih_term(
+        separation,
+        velocities,
+        gravity_constant * mass_b,
+        potentials,
+        newtonian_b,
+    ) / (speed_of_light * speed_of_light)
+}
+
+// 2.5 post-Newtonian radiation reaction, the back reaction of gravitational waves that makes close pairs inspiral
+// each pair is treated as an isolated binary (Damour-Deruelle form in harmonic coordinates) and the pairs are summed
+pub fn radiation_reaction_accelerations(
+    bodies: &BodyBuffer,
+    velocities: &[DVec3],
+    gravity_constant: f64,
+    speed_of_light: f64,
+    threads: usize,
+) -> Vec<DVec3> {
+    let n = bodies.len();
+    par_map_bodies(n, threads, |a| {
+        (0..n)
+            .filter(|&b| b != a)
+            .map(|b| {
+                radiation_reaction_pair(
+                    bodies.separation(a, b),
+                    velocities[a] - velocities[b],
+                    (bodies.mass[a], bodies.mass[b]),
+                    gravity_constant,
+                    speed_of_light,
+                )
+            })
+            .sum()
+    })
+}
+
+// radiation reaction on body a of an isolated pair with body b
+// separation and relative_velocity are a's position and velocity relative to b
+pub fn radiation_reaction_pair(
+    separation: DVec3,
+    relative_velocity: DVec3,
+    (mass_a, mass_b): (f64, f64),
+    gravity_constant: f64,
+    speed_of_light: f64,
+) -> DVec3 {
+    let r = separation.length();
+    let total_mass = mass_a + mass_b;
+    if r == 0. || total_mass == 0. {
+        return DVec3::ZERO;
+    }
+    let n_ab = separation / r;
+    let v = relative_velocity;
+    let gm_r = gravity_constant * total_mass / r;
+    // symmetric mass ratio, and the share of the relative acceleration that body a takes
+    let eta = mass_a * mass_b / (total_mass * total_mass);
+    let share = mass_b / total_mass;
+    let relative = 1.6 * eta * gm_r * gm_r / (r * speed_of_light.powi(5))
+        * ((3. * v.length_squared() + 17. / 3. * gm_r) * n_ab.dot(v) * n_ab
+            - (v.length_squared() + 3. * gm_r) * v);
+    share * relative
+}
+
+// the velocity change drag gives each body over a step, by index, and the kinetic energy it takes out
+// the changes are all worked out from the velocities at the start of the step, relative to where the medium was then
+pub fn drag_kicks(
+    bodies: &[(Body, DVec3, DVec3)],
+    dt: f64,
+    config: &Configuration,
+) -> (Vec<(usize, DVec3)>, f64) {
+    let mut kicks = Vec::new();
+    let mut dissipated = 0.;
+    if config.drag == Drag::None || dt <= 0. {
+        return (kicks, dissipated);
+    }
+    // indices of the bodies with an atmosphere
+    let atmospheres: Vec<usize> = if config.drag_atmosphere {
+        (0..bodies.len())
+            .filter(|&i| bodies[i].0.mass >= config.atmosphere_min_mass)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    for (index, &(body, position, velocity)) in bodies.iter().enumerate() {
+        // the densest atmosphere around the body, or the open medium which is at rest and can't be pushed
+        let (density, medium) = if config.drag_atmosphere {
+            let densest = atmospheres
+                .iter()
+                .filter(|&&i| i != index)
+                .map(|&i| {
+                    let (planet, center, _) = bodies[i];
+                    let altitude = (position.distance(center) - get_radius(planet)).max(0.);
+                    ((-altitude / config.atmosphere_scale_height).exp(), i)
+                })
+                .max_by(|(d1, _), (d2, _)| d1.total_cmp(d2));
+            match densest {
+                Some((density, i)) => (density, Some(i)),
+                None => continue,
+            }
+        } else {
+            (1., None)
+        };
+        let medium_velocity = medium.map_or(DVec3::ZERO, |i| bodies[i].2);
+        let relative = velocity - medium_velocity;
+        let rate = config.drag_coefficient * density * dt;
+        let slowed = match config.drag {
+            Drag::None => relative,
+            Drag::Linear => relative * (-rate).exp(),
+            Drag::Quadratic => relative / (1. + rate * relative.length()),
+        };
+        let change = slowed - relative;
+        // the atmosphere's body takes the opposite impulse, so the change in relative velocity is split by mass
+        // and the energy lost is that of the relative motion, in the pair's center of momentum frame
+        let reduced_mass = match medium {
+            Some(i) if body.mass + bodies[i].0.mass > 0. => {
+                let planet = bodies[i].0;
+                let total = body.mass + planet.mass;
+                kicks.push((index, change * planet.mass / total));
+                kicks.push((i, -change * body.mass / total));
+                body.mass * planet.mass / total
+            }
+            _ => {
+                kicks.push((index, change));
+                body.mass
+            }
+        };
+        dissipated += 0.5 * reduced_mass * (relative.length_squared() - slowed.length_squared());
+    }
+    (kicks, dissipated)
+}
+
+// time until a bound pair merges through gravitational wave emission, from Peters' formula for the
+// orbit's semi-major axis and eccentricity (the eccentric case uses the usual (1 - e^2)^(7/2) approximation)
+// None if the pair is unbound
+pub fn merger_time(
+    masses: (f64, f64),
+    separation: DVec3,
+    relative_velocity: DVec3,
+    gravity_constant: f64,
+    speed_of_light: f64,
+) -> Option<f64> {
+    let mu = gravity_constant * (masses.0 + masses.1);
+    let energy = relative_velocity.length_squared() / 2. - mu / separation.length();
+    if energy >= 0. || masses.0 * masses.1 == 0. {
+        return None;
+    }
+    let semi_major_axis = -mu / (2. * energy);
+    let angular_momentum = separation.cross(relative_velocity).length_squared();
+    let e2 = (1. + 2. * energy * angular_momentum / (mu * mu)).clamp(0., 1.);
+    let circular = 5. / 256. * speed_of_light.powi(5) * semi_major_axis.powi(4)
+        / (gravity_constant.powi(3) * masses.0 * masses.1 * (masses.0 + masses.1));
+    Some(circular * (1. - e2).powf(3.5))
+}
+
+// plus polarized gravitational wave strain from a pair, in the quadrupole approximation, as seen by an observer
+// at the given distance looking along the direction perpendicular to both polarization axes
+pub fn strain(
+    masses: (f64, f64),
+    separation: DVec3,
+    relative_velocity: DVec3,
+    distance: f64,
+    axes: (DVec3, DVec3),
+    gravity_constant: f64,
+    speed_of_light: f64,
+) -> f64 {
+    let total_mass = masses.0 + masses.1;
+    let reduced_mass = masses.0 * masses.1 / total_mass;
+    let r = separation.length();
+    if r == 0. || distance == 0. {
+        return 0.;
+    }
+    let acceleration = -gravity_constant * total_mass * separation / r.powi(3);
+    // second time derivative of the quadrupole moment projected on an axis
+    let quadrupole = |axis: DVec3| {
+        let (x, v, a) = (
+            separation.dot(axis),
+            relative_velocity.dot(axis),
+            acceleration.dot(axis),
+        );
+        2. * reduced_mass * (v * v + a * x)
+    };
+    gravity_constant / (speed_of_light.powi(4) * distance)
+        * (quadrupole(axes.0) - quadrupole(axes.1))
+}
+
+// acceleration at a position from the sum of the background fields
+pub fn external_acceleration(
+    potentials: &[ExternalPotential],
+    position: DVec3,
+    gravity_constant: f64,
+) -> DVec3 {
+    potentials
+        .iter()
+        .map(|potential| match *potential {
+            ExternalPotential::Uniform { acceleration } => DVec3::from_array(acceleration),
+            ExternalPotential::PointMass { mass, center } => {
+                let offset = position - DVec3::from_array(center);
+                let r = offset.length();
+                if r == 0. {
+                    return DVec3::ZERO;
+                }
+                -gravity_constant * mass * offset / r.powi(3)
+            }
+            ExternalPotential::LogarithmicHalo {
+                velocity,
+                core_radius,
+                center,
+            } => {
+                let offset = position - DVec3::from_array(center);
+                let d2 = core_radius * core_radius + offset.length_squared();
+                if d2 == 0. {
+                    return DVec3::ZERO;
+                }
+                -velocity * velocity * offset / d2
+            }
+            ExternalPotential::NfwHalo {
+                mass,
+                scale_radius,
+                center,
+            } => {
+                let offset = position - DVec3::from_array(center);
+                let r = offset.length();
+                if r == 0. {
+                    return DVec3::ZERO;
+                }
+                let x = r / scale_radius;
+                // mass enclosed within r, in units of the mass parameter
+                let enclosed = (1. + x).ln() - x / (1. + x);
+                -gravity_constant * mass * enclosed * offset / r.powi(3)
+            }
+            ExternalPotential::MiyamotoNagai {
+                mass,
+                radial_scale,
+                vertical_scale,
+                center,
+            } => {
+                let offset = position - DVec3::from_array(center);
+                let vertical = (offset.z * offset.z + vertical_scale * vertical_scale).sqrt();
+                let thickness = radial_scale + vertical;
+                let d3 =
+                    (offset.x * offset.x + offset.y * offset.y + thickness * thickness).powf(1.5);
+                if d3 == 0. {
+                    return DVec3::ZERO;
+                }
+                // a disk with no thickness pulls towards its plane from either side, and not at all within it
+                let pull = if vertical == 0. {
+                    0.
+                } else {
+                    offset.z * thickness / vertical
+                };
+                -gravity_constant * mass / d3 * DVec3::new(offset.x, offset.y, pull)
+            }
+            ExternalPotential::HarmonicTrap { frequency, center } => {
+                -frequency * frequency * (position - DVec3::from_array(center))
+            }
+        })
+        .sum()
+}
+
+// potential per unit mass of the external fields at a position, the acceleration being minus its gradient
+pub fn external_potential(
+    potentials: &[ExternalPotential],
+    position: DVec3,
+    gravity_constant: f64,
+) -> f64 {
+    potentials
+        .iter()
+        .map(|potential| match *potential {
+            ExternalPotential::Uniform { acceleration } => {
+                -DVec3::from_array(acceleration).dot(position)
+            }
+            ExternalPotential::PointMass { mass, center } => {
+                let r = position.distance(DVec3::from_array(center));
+                if r == 0. {
+                    return 0.;
+                }
+                -gravity_constant * mass / r
+            }
+            ExternalPotential::LogarithmicHalo {
+                velocity,
+                core_radius,
+                center,
+            } => {
+                let d2 = core_radius * core_radius
+                    + position.distance_squared(DVec3::from_array(center));
+                if d2 == 0. {
+                    return 0.;
+                }
+                0.5 * velocity * velocity * d2.ln()
+            }
+            ExternalPotential::NfwHalo {
+                mass,
+                scale_radius,
+                center,
+            } => {
+                let r = position.distance(DVec3::from_array(center));
+                // the limit at the center
+                if r == 0. {
+                    return -gravity_constant * mass / scale_radius;
+                }
+                -gravity_constant * mass * (1. + r / scale_radius).ln() / r
+            }
+            ExternalPotential::MiyamotoNagai {
+                mass,
+                radial_scale,
+                vertical_scale,
+                center,
+            } => {
+                let offset = position - DVec3::from_array(center);
+                let vertical = (offset.z * offset.z + vertical_scale * vertical_scale).sqrt();
+                let thickness = radial_scale + vertical;
+                let d = (offset.x * offset.x + offset.y * offset.y + thickness * thickness).sqrt();
+                if d == 0. {
+                    return 0.;
+                }
+                -gravity_constant * mass / d
+            }
+            ExternalPotential::HarmonicTrap { frequency, center } => {
+                0.5 * frequency * frequency * position.distance_squared(DVec3::from_array(center))
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::tasks::TaskPool;
+    use std::f64::consts::{PI, TAU};
+
+    // newtonian plus first post-Newtonian acceleration of every body
+    fn accelerations(
+        positions: &[DVec3],
+        velocities: &[DVec3],
+        masses: &[f64],
+        speed_of_light: f64,
+    ) -> Vec<DVec3> {
+        let mut buffer = BodyBuffer::default();
+        buffer.gather(positions.iter().copied().zip(masses.iter().copied()));
+        let newtonian = gravity_accelerations(&buffer, 1., 1);
+        let corrections =
+            post_newtonian_accelerations(&buffer, velocities, &newtonian, 1., speed_of_light, 1);
+        newtonian
+            .iter()
+            .zip(corrections)
+            .map(|(a, c)| *a + c)
+            .collect()
+    }
+
+    // a light body on an eccentric orbit around a heavy one should see its periapsis advance by
+    // 6 pi G M / (c^2 a (1 - e^2)) per orbit, as in general relativity
+    #[test]
+    fn periapsis_precession_matches_general_relativity() {
+        ComputeTaskPool::get_or_init(TaskPool::default);
+        let masses = [1., 1e-7];
+        let semi_major_axis = 1.;
+        let eccentricity = 0.5;
+        let speed_of_light = 20.;
+        let orbits = 4;
+        let dt = 2e-3;
+
+        // start the light body at periapsis
+        let periapsis = semi_major_axis * (1. - eccentricity);
+        let mut positions = vec![DVec3::ZERO, DVec3::new(periapsis, 0., 0.)];
+        let mut velocities = vec![
+            DVec3::ZERO,
+            DVec3::new(0., ((1. + eccentricity) / periapsis).sqrt(), 0.),
+        ];
+
+        // runge-kutta 4 until the light body has passed periapsis the given number of times
+        let mut angle = 0.;
+        let mut last_angle = 0.;
+        let mut passes = 0;
+        while passes < orbits {
+            let derivative = |p: &[DVec3], v: &[DVec3]| {
+                (v.to_vec(), accelerations(p, v, &masses, speed_of_light))
+            };
+            let step = |p: &[DVec3], d: &[DVec3], h: f64| -> Vec<DVec3> {
+                p.iter().zip(d).map(|(p, d)| *p + *d * h).collect()
+            };
+            let (k1x, k1v) = derivative(&positions, &velocities);
+            let (k2x, k2v) = derivative(
+                &step(&positions, &k1x, dt / 2.),
+                &step(&velocities, &k1v, dt / 2.),
+            );
+            let (k3x, k3v) = derivative(
+                &step(&positions, &k2x, dt / 2.),
+                &step(&velocities, &k2v, dt / 2.),
+            );
+            let (k4x, k4v) = derivative(&step(&positions, &k3x, dt), &step(&velocities, &k3v, dt));
+            let combine = |y: &[DVec3], k1: &[DVec3], k2: &[DVec3], k3: &[DVec3], k4: &[DVec3]| {
+                (0..y.len())
+                    .map(|i| y[i] + (k1[i] + 2. * k2[i] + 2. * k3[i] + k4[i]) * dt / 6.)
+                    .collect::<Vec<DVec3>>()
+            };
+            let new_positions = combine(&positions, &k1x, &k2x, &k3x, &k4x);
+            let new_velocities = combine(&velocities, &k1v, &k2v, &k3v, &k4v);
+
+            // periapsis is where the radial velocity turns from inward to outward
+            let radial_speed = |p: &[DVec3], v: &[DVec3]| (p[1] - p[0]).dot(v[1] - v[0]);
+            let before = radial_speed(&positions, &velocities);
+            let after = radial_speed(&new_positions, &new_velocities);
+            if before < 0. && after >= 0. {
+                let t = before / (before - after);
+                let offset =
+                    (positions[1] - positions[0]).lerp(new_positions[1] - new_positions[0], t);
+                let current = offset.y.atan2(offset.x);
+                // unwrap across the -pi to pi seam
+                angle += (current - last_angle + PI).rem_euclid(TAU) - PI;
+                last_angle = current;
+                passes += 1;
+            }
+            positions = new_positions;
+            velocities = new_velocities;
+        }
+
+        let measured = angle / orbits as f64;
+        let expected =
+            3. * TAU / (speed_of_light.powi(2) * semi_major_axis * (1. - eccentricity.powi(2)));
+        assert!(
+            (measured - expected).abs() < 0.05 * expected,
+            "precession per orbit {measured}, expected {expected}"
+        );
+    }
+
+    fn kinetic_energy(bodies: &[(Body, DVec3, DVec3)]) -> f64 {
+        bodies
+            .iter()
+            .map(|(body, _, velocity)| 0.5 * body.mass * velocity.length_squared())
+            .sum()
+    }
+
+    // whatever kinetic energy drag takes out of the bodies is exactly what it reports as dissipated
+    fn assert_drag_accounted(bodies: &mut [(Body, DVec3, DVec3)], config: &Configuration) {
+        let start = kinetic_energy(bodies);
+        let mut dissipated = 0.;
+        for _ in 0..100 {
+            let (kicks, lost) = drag_kicks(bodies, 0.05, config);
+            assert!(!kicks.is_empty());
+            for (index, kick) in kicks {
+                bodies[index].2 += kick;
+            }
+            dissipated += lost;
+        }
+        assert!(dissipated > 0.);
+        let end = kinetic_energy(bodies);
+        assert!(
+            ((end + dissipated) - start).abs() <= 1e-12 * start,
+            "{end} + {dissipated} vs {start}"
+        );
+    }
+
+    #[test]
+    fn drag_energy_is_accounted_for() {
+        let body = |mass: f64, position: DVec3, velocity: DVec3| {
+            (Body { mass, density: 1. }, position, velocity)
+        };
+        for drag in [Drag::Linear, Drag::Quadratic] {
+            // the open medium is at rest and takes the whole kinetic energy of the slowed motion
+            let mut open = [
+                body(1., DVec3::ZERO, DVec3::new(1., 2., 0.)),
+                body(3., DVec3::X, DVec3::new(-0.5, 0., 0.3)),
+                body(0., DVec3::Y, DVec3::new(0., -4., 0.)),
+            ];
+            let config = Configuration {
+                drag,
+                ..Configuration::default()
+            };
+            assert_drag_accounted(&mut open, &config);
+            // a satellite skimming a planet's atmosphere drags the planet along too
+            let mut atmosphere = [
+                body(20., DVec3::ZERO, DVec3::new(0.2, 0., 0.)),
+                body(1., DVec3::new(2.5, 0., 0.), DVec3::new(0., 1.5, 0.4)),
+            ];
+            let config = Configuration {
+                drag,
+                drag_atmosphere: true,
+                ..Configuration::default()
+            };
+            assert_drag_accounted(&mut atmosphere, &config);
+            // and the drag can't push them anywhere as a whole
+            let momentum: DVec3 = atmosphere.iter().map(|(b, _, v)| b.mass * *v).sum();
+            assert!((momentum - DVec3::new(4., 1.5, 0.4)).length() < 1e-12);
+        }
+    }
+
+    // a few bodies scattered through a box of side 10, not a multiple of LANES, with one pair on top of each other
+    fn scattered(n: usize) -> Vec<(DVec3, f64)> {
+        let mut bodies: Vec<(DVec3, f64)> = (0..n)
+            .map(|i| {
+                let t = i as f64;
+                (
+                    DVec3::new((t * 2.3).sin(), (t * 1.7 + 1.).cos(), (t * 0.9 + 2.).sin()) * 4.9,
+                    1. + (t * 0.7).cos().abs(),
+                )
+            })
+            .collect();
+        bodies[5].0 = bodies[2].0;
+        bodies
+    }
+
+    // the vectorized kernel against the plain pairwise sum, with and without the periodic box
+    #[test]
+    fn kernel_matches_the_pairwise_sum() {
+        ComputeTaskPool::get_or_init(TaskPool::default);
+        let bodies = scattered(7);
+        for period in [None, Some(10.)] {
+            let mut buffer = BodyBuffer::default();
+            buffer.gather(bodies.iter().copied());
+            buffer.period = period;
+            let accelerations = gravity_accelerations(&buffer, 1.3, 1);
+            for (i, &(p, _)) in bodies.iter().enumerate() {
+                let (mut sum, mut scale) = (DVec3::ZERO, 0.);
+                for &(q, m) in &bodies {
+                    let offset = minimum_image(q - p, period);
+                    if offset != DVec3::ZERO {
+                        let term = 1.3 * m * offset / offset.length().powi(3);
+                        sum += term;
+                        scale += term.length();
+                    }
+                }
+                assert!(
+                    (accelerations[i] - sum).length() <= 1e-12 * scale,
+                    "{i}: {0} vs {sum}",
+                    accelerations[i]
+                );
+            }
+        }
+    }
+
+    // every body is summed on its own, so how the bodies are split between threads can't change a bit
+    #[test]
+    fn accelerations_do_not_depend_on_the_threads() {
+        ComputeTaskPool::get_or_init(TaskPool::default);
+        let mut buffer = BodyBuffer::default();
+        buffer.gather(scattered(13).into_iter());
+        let single = gravity_accelerations(&buffer, 1.3, 1);
+        for threads in [0, 2, 3, 5, 13, 20] {
+            assert_eq!(gravity_accelerations(&buffer, 1.3, threads), single);
+        }
+    }
+
+    #[test]
+    fn reflecting_box_mirrors_the_step() {
+        // one wall: the path from inside to past it comes back as its mirror image
+        let (mut position, mut previous, mut velocity) = (
+            DVec3::new(13., 2., -3.),
+            DVec3::new(9., 2., -3.),
+            DVec3::new(4., 0., 1.),
+        );
+        reflect_into_box(&mut position, &mut previous, &mut velocity, 10.);
+        assert_eq!(position, DVec3::new(7., 2., -3.));
+        assert_eq!(previous, DVec3::new(11., 2., -3.));
+        assert_eq!(velocity, DVec3::new(-4., 0., 1.));
+        // three walls on x, two on y: only an odd number turns the velocity round
+        let (mut position, mut previous, mut velocity) = (
+            DVec3::new(55., -42., 0.),
+            DVec3::new(50., -41., 0.),
+            DVec3::new(5., -1., 0.),
+        );
+        reflect_into_box(&mut position, &mut previous, &mut velocity, 10.);
+        assert_eq!(position, DVec3::new(5., -2., 0.));
+        assert_eq!(previous, DVec3::new(10., -1., 0.));
+        assert_eq!(velocity, DVec3::new(-5., -1., 0.));
+        assert_eq!(
+            position.distance(previous),
+            DVec3::new(5., -1., 0.).length()
+        );
+    }
+
+    #[test]
+    fn periodic_box_wraps_the_step() {
+        let (mut position, mut previous) = (DVec3::new(12., -31., 4.), DVec3::new(9., -29., 4.));
+        wrap_into_box(&mut position, &mut previous, 10.);
+        assert_eq!(position, DVec3::new(-8., 9., 4.));
+        assert_eq!(previous, DVec3::new(-11., 11., 4.));
+    }
+}
@@ -1,11 +1,29 @@
-use crate::helpers;
-use bevy::prelude::*;
+use std::{collections::VecDeque, fs};
+
+use crate::{
+    components::Body,
+    forces::{BodyForce, PairForce},
+    helpers,
+    units::UnitSystem,
+};
+use bevy::{
+    math::{DQuat, DVec3},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum SpawnSelectionMode {
     None,
     Size,
     Speed,
+    Mass,
+    Density,
+    Count,
+    Spread,
+    Dispersion,
+    Eccentricity,
+    Charge,
     Fire,
 }
 
@@ -15,11 +33,76 @@ impl Default for SpawnSelectionMode {
     }
 }
 
+// how the bodies of one shot are arranged around the spawn point
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum SpawnPattern {
+    // a row along the view direction, spread long
+    #[default]
+    Line,
+    // uniformly inside a ball with the spread as radius
+    Cluster,
+    // uniformly on a sphere with the spread as radius
+    Shell,
+    // evenly around a circle with the spread as radius, lying flat in front of the camera
+    Ring,
+}
+
+impl SpawnPattern {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Line => Self::Cluster,
+            Self::Cluster => Self::Shell,
+            Self::Shell => Self::Ring,
+            Self::Ring => Self::Line,
+        }
+    }
+}
+
+// how the velocity of a spawned body is chosen
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum OrbitMode {
+    // fly along the camera's forward direction at the spawn speed
+    #[default]
+    Straight,
+    // orbit the target body at the periapsis of an orbit with the spawn eccentricity
+    // circular and escape are the special cases of eccentricity 0 and 1
+    Circular,
+    Eccentric,
+    Escape,
+}
+
+impl OrbitMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Straight => Self::Circular,
+            Self::Circular => Self::Eccentric,
+            Self::Eccentric => Self::Escape,
+            Self::Escape => Self::Straight,
+        }
+    }
+}
+
 #[derive(Resource, Clone, Copy)]
 pub struct BodySpawningOptions {
     pub mode: SpawnSelectionMode,
     pub radius: f32,
     pub speed: f32,
+    // mass follows from radius and density
+    pub density: f32,
+    // bodies per shot, rounded when spawning so fine scrolling still adds up
+    pub count: f32,
+    pub pattern: SpawnPattern,
+    // size of the pattern
+    pub spread: f32,
+    // largest random velocity added to each body
+    pub dispersion: f32,
+    pub orbit: OrbitMode,
+    pub eccentricity: f32,
+    // spawn here instead of in front of the camera
+    pub pinned_position: Option<DVec3>,
+    // spawn massless test particles instead of bodies
+    pub test_particles: bool,
+    pub charge: f32,
 }
 
 // override default values for size and speed (f32 default is 0)
@@ -29,9 +112,442 @@ impl Default for BodySpawningOptions {
             mode: Default::default(),
             radius: helpers::get_default_sphere_radius(),
             speed: 1.,
+            density: 1.,
+            count: 1.,
+            pattern: Default::default(),
+            spread: 3.,
+            dispersion: 0.,
+            orbit: Default::default(),
+            eccentricity: 0.5,
+            pinned_position: None,
+            test_particles: false,
+            charge: 0.,
+        }
+    }
+}
+
+impl BodySpawningOptions {
+    // the defaults scaled to the unit system's scene, like Configuration::for_units
+    pub fn for_units(units: UnitSystem) -> Self {
+        let (length, mass, time) = units.scene();
+        let defaults = Self::default();
+        Self {
+            radius: defaults.radius * length as f32,
+            speed: defaults.speed * (length / time) as f32,
+            density: defaults.density * (mass / length.powi(3)) as f32,
+            spread: defaults.spread * length as f32,
+            dispersion: defaults.dispersion * (length / time) as f32,
+            ..defaults
+        }
+    }
+
+    pub fn mass(&self) -> f64 {
+        helpers::get_mass(self.radius as f64, self.density as f64)
+    }
+
+    // presets are kept in the built-in scene's units, so they stand for the same bodies in every unit system
+    pub fn apply_preset(&mut self, preset: SpawnPreset, units: UnitSystem) {
+        let (length, mass, time) = units.scene();
+        self.radius = preset.radius * length as f32;
+        self.speed = preset.speed * (length / time) as f32;
+        self.density = preset.density * (mass / length.powi(3)) as f32;
+        self.count = preset.count;
+    }
+
+    pub fn preset(&self, units: UnitSystem) -> SpawnPreset {
+        let (length, mass, time) = units.scene();
+        SpawnPreset {
+            name: format!(
+                "r {0}, v {1}, rho {2}, x{3}",
+                units.length(self.radius as f64),
+                units.speed(self.speed as f64),
+                units.density(self.density as f64),
+                self.count.round()
+            ),
+            radius: self.radius / length as f32,
+            speed: self.speed / (length / time) as f32,
+            density: self.density / (mass / length.powi(3)) as f32,
+            count: self.count,
+        }
+    }
+}
+
+// a saved set of spawn options, in the built-in scene's units
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SpawnPreset {
+    pub name: String,
+    pub radius: f32,
+    pub speed: f32,
+    pub density: f32,
+    pub count: f32,
+}
+
+// spawn presets, kept in spawn_presets.json next to the executable so they persist between runs
+#[derive(Resource, Clone)]
+pub struct SpawnPresets {
+    pub presets: Vec<SpawnPreset>,
+    // the preset last applied, if any
+    pub current: Option<usize>,
+}
+
+impl Default for SpawnPresets {
+    fn default() -> Self {
+        let preset = |name: &str, radius, speed, density, count| SpawnPreset {
+            name: name.into(),
+            radius,
+            speed,
+            density,
+            count,
+        };
+        Self {
+            presets: vec![
+                preset("default", helpers::get_default_sphere_radius(), 1., 1., 1.),
+                preset("pebble", 0.1, 5., 1., 1.),
+                preset("dense moon", 0.3, 2., 8., 1.),
+                preset("gas giant", 2., 0.5, 0.25, 1.),
+                preset("swarm", 0.1, 3., 1., 10.),
+            ],
+            current: None,
+        }
+    }
+}
+
+impl SpawnPresets {
+    const FILE_NAME: &'static str = "spawn_presets.json";
+
+    // read the saved presets, falling back to the built in ones
+    pub fn load() -> Self {
+        let Some(path) = helpers::executable_dir_file(Self::FILE_NAME) else {
+            return Self::default();
+        };
+        let Ok(data) = fs::read_to_string(path) else {
+            // file doesn't exist. That's fine, just use the default presets
+            return Self::default();
+        };
+        match serde_json::from_str(&data) {
+            Ok(presets) => Self {
+                presets,
+                current: None,
+            },
+            Err(_) => {
+                println!(
+                    "Could not read your {} into json, using default presets",
+                    Self::FILE_NAME
+                );
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let Some(path) = helpers::executable_dir_file(Self::FILE_NAME) else {
+            return;
+        };
+        let data = serde_json::to_string_pretty(&self.presets).expect("presets serialize to json");
+        if let Err(e) = fs::write(path, data) {
+            println!("Could not save spawn presets to {}: {e}", Self::FILE_NAME);
         }
     }
 }
 
+// state of the drag to aim spawning tool
+#[derive(Resource, Clone, Copy, Default)]
+pub struct Slingshot {
+    pub active: bool,
+    // where the body being aimed will spawn, while the mouse button is held
+    pub anchor: Option<DVec3>,
+    // launch velocity dragged out so far
+    pub velocity: DVec3,
+    // add the reference body's velocity on release
+    pub inherit_velocity: bool,
+}
+
 #[derive(Resource, Clone, Default)]
 pub struct SphereInfo(pub Handle<Mesh>, pub Handle<StandardMaterial>);
+
+// pair interaction throughput of the gravity kernel and of the old per pair query loop, filled in benchmark mode
+#[derive(Resource, Default)]
+pub struct Benchmark {
+    // pair interactions per second as of the last report
+    pub kernel_rate: f64,
+    pub query_loop_rate: f64,
+    // accumulated since the last report
+    pairs: f64,
+    kernel_seconds: f64,
+    query_loop_seconds: f64,
+    elapsed: f64,
+}
+
+impl Benchmark {
+    // seconds of real time between reports
+    const REPORT_INTERVAL: f64 = 1.;
+
+    pub fn record(
+        &mut self,
+        pairs: f64,
+        kernel_seconds: f64,
+        query_loop_seconds: f64,
+        real_delta: f64,
+    ) {
+        self.pairs += pairs;
+        self.kernel_seconds += kernel_seconds;
+        self.query_loop_seconds += query_loop_seconds;
+        self.elapsed += real_delta;
+        if self.elapsed < Self::REPORT_INTERVAL {
+            return;
+        }
+        if self.kernel_seconds > 0. && self.query_loop_seconds > 0. {
+            self.kernel_rate = self.pairs / self.kernel_seconds;
+            self.query_loop_rate = self.pairs / self.query_loop_seconds;
+            info!(
+                "pair interactions/s: kernel {:.3e}, query loop {:.3e} ({:.1}x)",
+                self.kernel_rate,
+                self.query_loop_rate,
+                self.kernel_rate / self.query_loop_rate
+            );
+        }
+        *self = Self {
+            kernel_rate: self.kernel_rate,
+            query_loop_rate: self.query_loop_rate,
+            ..default()
+        };
+    }
+}
+
+// the most recent collisions, newest last, as shown in the on-screen log
+#[derive(Resource, Clone, Default)]
+pub struct CollisionLog(pub VecDeque<String>);
+
+// kinetic energy taken out of the bodies by dissipative forces since the last reset, so energy still adds up
+#[derive(Resource, Default)]
+pub struct Dissipation {
+    pub drag: f64,
+}
+
+// simulated time, which runs at the virtual time's rate but can also run backwards or advance one step while paused
+#[derive(Resource, Default)]
+pub struct SimulationClock {
+    // length of this frame's physics step, negative when running backwards and 0 when nothing moves
+    pub dt: f64,
+    // simulated time since startup
+    pub elapsed: f64,
+    pub reversed: bool,
+    // take one step on the next frame even though time is paused
+    pub single_step: bool,
+}
+
+// the whole simulation after one physics step
+// bodies are kept with their entity so rewinding writes them back into the same entities
+// massless test particles aren't kept, rewinding leaves them where they are
+pub struct Snapshot {
+    pub elapsed: f64,
+    pub drag: f64,
+    pub bodies: Vec<(Entity, BodyState, Option<DVec3>, DQuat)>,
+}
+
+// recent physics steps, oldest first, to rewind through
+#[derive(Resource, Default)]
+pub struct Rewind {
+    pub snapshots: VecDeque<Snapshot>,
+    // the snapshot the world has been rewound to, if it has been
+    pub cursor: Option<usize>,
+    // the body list of the last dropped snapshot, reused for the next one so recording doesn't allocate every step
+    pub spare: Vec<(Entity, BodyState, Option<DVec3>, DQuat)>,
+}
+
+impl Rewind {
+    // stepping on from a rewound state starts a new future, so the old one is dropped
+    // the oldest steps go once there are more than length of them or more than body_limit body states in all,
+    // but the latest is always kept
+    pub fn record(&mut self, snapshot: Snapshot, length: usize, body_limit: usize) {
+        if let Some(cursor) = self.cursor.take() {
+            self.snapshots.truncate(cursor + 1);
+        }
+        self.snapshots.push_back(snapshot);
+        let mut stored: usize = self.snapshots.iter().map(|s| s.bodies.len()).sum();
+        while self.snapshots.len() > 1 && (self.snapshots.len() > length || stored > body_limit) {
+            let Some(oldest) = self.snapshots.pop_front() else {
+                break;
+            };
+            stored -= oldest.bodies.len();
+            self.spare = oldest.bodies;
+        }
+    }
+
+    // a body brought back after it was despawned gets a new entity
+    pub fn remap(&mut self, old: Entity, new: Entity) {
+        for snapshot in &mut self.snapshots {
+            for (entity, ..) in &mut snapshot.bodies {
+                if *entity == old {
+                    *entity = new;
+                }
+            }
+        }
+    }
+}
+
+// the binary followed by the gravitational wave overlay and the strain it has produced
+// as (simulation time, strain) after each physics step, oldest first
+#[derive(Resource, Default)]
+pub struct GravitationalWaves {
+    pub pair: Option<(Entity, Entity)>,
+    pub strain: VecDeque<(f64, f64)>,
+}
+
+// the forces summed into every body's acceleration, added with AddForces
+#[derive(Resource, Default)]
+pub struct ForceLaws {
+    pub pair: Vec<Box<dyn PairForce>>,
+    pub body: Vec<Box<dyn BodyForce>>,
+}
+
+// everything needed to bring a body back exactly as it was
+#[derive(Clone, Copy)]
+pub struct BodyState {
+    pub body: Body,
+    pub position: DVec3,
+    pub velocity: DVec3,
+    pub spin: DVec3,
+    pub charge: f64,
+}
+
+// a body added or removed by an edit, with everything needed to bring it back as it was
+#[derive(Clone, Copy)]
+pub struct EditedBody {
+    pub entity: Entity,
+    pub state: BodyState,
+    pub acceleration: Option<DVec3>,
+    pub orientation: DQuat,
+    pub selected: bool,
+}
+
+impl EditedBody {
+    // a body just spawned, which hasn't been stepped, turned or selected yet
+    pub fn spawned(entity: Entity, state: BodyState) -> Self {
+        Self {
+            entity,
+            state,
+            acceleration: None,
+            orientation: DQuat::IDENTITY,
+            selected: false,
+        }
+    }
+}
+
+// a change the user made to the set of bodies: the removed bodies were despawned and the added ones spawned
+// spawning is an edit with only added bodies, deleting one with only removed bodies, and a reset has both
+#[derive(Clone, Default)]
+pub struct WorldEdit {
+    pub removed: Vec<EditedBody>,
+    pub added: Vec<EditedBody>,
+    // a reset also clears the energy lost to drag, this is the other side's value, swapped in on undo and redo
+    pub dissipation: Option<f64>,
+    // and puts the camera back at the start, as (position, transform) this is swapped in the same way
+    pub camera: Option<(DVec3, Transform)>,
+}
+
+// undo and redo stacks of world edits, the oldest edits are forgotten past the configured length
+#[derive(Resource, Default)]
+pub struct EditHistory {
+    undo: VecDeque<WorldEdit>,
+    redo: Vec<WorldEdit>,
+}
+
+impl EditHistory {
+    // a new edit makes the redo stack meaningless
+    pub fn record(&mut self, edit: WorldEdit, limit: usize) {
+        self.redo.clear();
+        self.undo.push_back(edit);
+        while self.undo.len() > limit {
+            self.undo.pop_front();
+        }
+    }
+
+    pub fn pop_undo(&mut self) -> Option<WorldEdit> {
+        self.undo.pop_back()
+    }
+
+    pub fn pop_redo(&mut self) -> Option<WorldEdit> {
+        self.redo.pop()
+    }
+
+    pub fn push_undo(&mut self, edit: WorldEdit) {
+        self.undo.push_back(edit);
+    }
+
+    pub fn push_redo(&mut self, edit: WorldEdit) {
+        self.redo.push(edit);
+    }
+
+    // whether any stored edit adds or removes the body
+    pub fn refers_to(&self, entity: Entity) -> bool {
+        self.undo
+            .iter()
+            .chain(self.redo.iter())
+            .flat_map(|edit| edit.removed.iter().chain(edit.added.iter()))
+            .any(|body| body.entity == entity)
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    // bodies get new entities when they are respawned, so point every stored edit at the new one
+    pub fn remap(&mut self, old: Entity, new: Entity) {
+        for edit in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+            for body in edit.removed.iter_mut().chain(edit.added.iter_mut()) {
+                if body.entity == old {
+                    body.entity = new;
+                }
+            }
+        }
+    }
+}
+
+// what the view (and optionally the state) is measured relative to
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum FrameMode {
+    #[default]
+    Inertial,
+    // follows the center of mass of every body
+    Barycentric,
+    // follows the selected body, or the heaviest if none is selected
+    BodyCentered,
+    // follows and turns with the selected body and the body pulling hardest on it,
+    // keeping the pair on the x axis with their orbit in the xy plane
+    CoRotating,
+}
+
+impl FrameMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Inertial => Self::Barycentric,
+            Self::Barycentric => Self::BodyCentered,
+            Self::BodyCentered => Self::CoRotating,
+            Self::CoRotating => Self::Inertial,
+        }
+    }
+}
+
+// the current reference frame, the camera is carried along with it so the view stays put in the frame
+#[derive(Resource, Clone, Copy, Default)]
+pub struct ReferenceFrame {
+    pub mode: FrameMode,
+    // shift the bodies themselves so the frame's origin stays at rest at the origin (barycentric and body centered only)
+    pub apply_to_state: bool,
+    // origin, orientation and motion of the frame in simulation space
+    pub origin: DVec3,
+    pub rotation: DQuat,
+    pub velocity: DVec3,
+    pub angular_velocity: DVec3,
+    // the frame as of the last update, to move the camera along with it, along with the mode and bodies it was built from
+    pub last: Option<(FrameMode, Entity, DVec3, DQuat)>,
+}
+
+impl ReferenceFrame {
+    // velocity of a point at rest in the frame
+    pub fn velocity_at(&self, position: DVec3) -> DVec3 {
+        self.velocity + self.angular_velocity.cross(position - self.origin)
+    }
+}
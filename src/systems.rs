@@ -1,18 +1,46 @@
 use bevy::{
     input::mouse::{MouseMotion, MouseWheel},
-    math::NormedVectorSpace,
+    math::{DMat3, DQuat, DVec3},
     prelude::*,
     window::PrimaryWindow,
 };
 
-use crate::resources::{BodySpawningOptions, SpawnSelectionMode, SphereInfo};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    hint::black_box,
+    io::{BufWriter, Write},
+    time::Instant,
+};
+
+use crate::resources::{
+    Benchmark, BodySpawningOptions, BodyState, CollisionLog, Dissipation, EditHistory, EditedBody,
+    ForceLaws, FrameMode, GravitationalWaves, OrbitMode, ReferenceFrame, Rewind, SimulationClock,
+    Slingshot, Snapshot, SpawnPattern, SpawnPresets, SpawnSelectionMode, SphereInfo, WorldEdit,
+};
 use crate::{
-    components::{Body, HelpText, HelpUI, Position, SpawnText, SpawnUI, Velocity},
-    helpers::{body_bundle, uv_debug_texture},
+    collision::{overlapping_pairs, time_of_contact},
+    config::{Boundary, Configuration, Drag, GravityLaw},
+    events::{CollisionEvent, EscapeEvent},
+    forces::{circular_speed, mechanical_energy},
+    helpers::{get_radius, merged_density, moment_of_inertia, roche_limit, tidal_fragments},
+    physics::{
+        drag_kicks, gravity_accelerations, merger_time, minimum_image, reflect_into_box, strain,
+        wrap_into_box, BodyBuffer,
+    },
+    units::UnitSystem,
 };
 use crate::{
-    config::Configuration,
-    helpers::{get_mass, get_radius},
+    components::{
+        Acceleration, Body, CameraPosition, Charge, CollisionLogText, CollisionLogUI,
+        GravitationalWaveText, GravitationalWaveUI, HelpText, HelpUI, ImpactFlash, Orientation,
+        Position, PreviousPosition, Selected, SpawnText, SpawnUI, Spin, TestParticle,
+        TidalFragment, TimelineFill, TimelineText, TimelineUI, Velocity,
+    },
+    helpers::{
+        center_of_mass, orbit_velocity, pattern_offsets, ray_sphere_distance, spawn_body,
+        uv_debug_texture, Rng,
+    },
 };
 
 pub fn text_section(color: Color, value: &str) -> TextSection {
@@ -32,6 +60,203 @@ pub fn scale_ui(window: Query<&Window, With<PrimaryWindow>>, mut ui_scale: ResMu
     ui_scale.0 = primary_window.height() / 1440.;
 }
 
+// number of pages the controls are split into, so each fits on the screen
+const HELP_PAGES: usize = 3;
+
+fn help_page(page: usize) -> Vec<TextSection> {
+    match page {
+        0 => vec![
+            TextSection::new(
+                "Controls (1/3)",
+                TextStyle {
+                    font_size: 72.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            TextSection::new(
+                "\n\nGeneral",
+                TextStyle {
+                    font_size: 60.,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            text_section(
+                Color::WHITE,
+                "\nH to turn to the next page of this help, and hide it after the last",
+            ),
+            text_section(Color::WHITE, "\nR to reset the simulation"),
+            text_section(Color::WHITE, "\nCtrl+Z to undo, Ctrl+Y to redo"),
+            text_section(Color::WHITE, "\nEsc to quit"),
+            text_section(
+                Color::WHITE,
+                "\nShift to increase speed (of any other control)",
+            ),
+            text_section(
+                Color::WHITE,
+                "\nAlt to decrease speed (of any other control)",
+            ),
+            TextSection::new(
+                "\n\nMovement",
+                TextStyle {
+                    font_size: 60.,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            text_section(Color::WHITE, "\nWASD to move laterally"),
+            text_section(Color::WHITE, "\nMouse to look"),
+            text_section(
+                Color::WHITE,
+                "\nTab to cycle the reference frame (inertial, barycentric, body centered, co-rotating)",
+            ),
+            text_section(
+                Color::WHITE,
+                "\nEnter to toggle applying the reference frame to the bodies",
+            ),
+            TextSection::new(
+                "\n\nTime",
+                TextStyle {
+                    font_size: 60.,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            text_section(Color::WHITE, "\nP to pause time"),
+            text_section(Color::WHITE, "\nEquals key to increase simulation rate"),
+            text_section(Color::WHITE, "\nHyphen key to decrease simulation rate"),
+            text_section(Color::WHITE, "\nPeriod to advance a single step while paused"),
+            text_section(Color::WHITE, "\nComma to run time backwards"),
+            text_section(
+                Color::WHITE,
+                "\nHold left or right bracket to scrub back or forward through recent steps",
+            ),
+        ],
+        1 => vec![
+            TextSection::new(
+                "Controls (2/3)",
+                TextStyle {
+                    font_size: 72.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            TextSection::new(
+                "\n\nSpawning",
+                TextStyle {
+                    font_size: 60.,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            text_section(Color::WHITE, "\nF or click middle mouse to spawn a body"),
+            text_section(
+                Color::WHITE,
+                "\nScroll mouse wheel to modify selected spawn option",
+            ),
+            text_section(Color::WHITE, "\nHold B to stream bodies"),
+            text_section(
+                Color::WHITE,
+                "\nBackquote to toggle spawning massless test particles",
+            ),
+            text_section(
+                Color::WHITE,
+                "\nSemicolon to select spawn charge, charged bodies attract or repel",
+            ),
+            text_section(Color::WHITE, "\nLeft click to select spawn speed"),
+            text_section(Color::WHITE, "\nRight click to select spawn size"),
+            text_section(
+                Color::WHITE,
+                "\nM, N or C to select spawn mass, density or bodies per shot",
+            ),
+            text_section(
+                Color::WHITE,
+                "\nQ to cycle spawn presets, K to save the current options as one",
+            ),
+            text_section(
+                Color::WHITE,
+                "\nL to cycle the spawn pattern (line, cluster, shell, ring)",
+            ),
+            text_section(
+                Color::WHITE,
+                "\nU or J to select pattern spread or velocity dispersion",
+            ),
+            text_section(
+                Color::WHITE,
+                "\nO to cycle the spawn orbit (straight, circular, eccentric, escape)",
+            ),
+            text_section(Color::WHITE, "\nE to select orbit eccentricity"),
+            text_section(
+                Color::WHITE,
+                "\nT to select the body in the center of the view as the orbit target",
+            ),
+            text_section(
+                Color::WHITE,
+                "\nG to pin the spawn point in front of the camera, or unpin it",
+            ),
+            text_section(
+                Color::WHITE,
+                "\nV to toggle the slingshot, then click and drag to aim and scroll to push it forward",
+            ),
+            text_section(
+                Color::WHITE,
+                "\nI to toggle slingshot bodies inheriting the target's velocity",
+            ),
+            text_section(
+                Color::WHITE,
+                "\nX to delete the body in the center of the view",
+            ),
+        ],
+        _ => vec![
+            TextSection::new(
+                "Controls (3/3)",
+                TextStyle {
+                    font_size: 72.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            TextSection::new(
+                "\n\nPhysics",
+                TextStyle {
+                    font_size: 60.,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ),
+            text_section(
+                Color::WHITE,
+                "\n1 to toggle first post-Newtonian (relativistic) corrections",
+            ),
+            text_section(
+                Color::WHITE,
+                "\n2 to toggle gravitational wave radiation reaction, select a body to watch it and its companion inspiral",
+            ),
+            text_section(
+                Color::WHITE,
+                "\n3 to toggle tidal disruption of bodies inside the Roche limit of a much heavier one",
+            ),
+            text_section(
+                Color::WHITE,
+                "\n4 to cycle the boundary (none, reflecting box, periodic box, escape sphere)",
+            ),
+            text_section(
+                Color::WHITE,
+                "\n5 to cycle the gravity law (Newtonian, power law, Yukawa, MOND)",
+            ),
+            text_section(
+                Color::WHITE,
+                "\n6 to cycle drag (none, linear, quadratic), 7 to toggle it being confined to atmospheres",
+            ),
+            text_section(
+                Color::WHITE,
+                "\n8 to show or hide the total energy, with what drag has taken out",
+            ),
+        ],
+    }
+}
+
 pub fn spawn_help(mut commands: Commands) {
     commands
         .spawn((
@@ -51,88 +276,33 @@ pub fn spawn_help(mut commands: Commands) {
         ))
         .with_children(|c| {
             c.spawn((
-                TextBundle::from_sections([
-                    TextSection::new(
-                        "Controls",
-                        TextStyle {
-                            font_size: 72.0,
-                            color: Color::WHITE,
-                            ..default()
-                        },
-                    ),
-                    TextSection::new(
-                        "\n\nGeneral",
-                        TextStyle {
-                            font_size: 60.,
-                            color: Color::WHITE,
-                            ..default()
-                        },
-                    ),
-                    text_section(Color::WHITE, "\nH to show or hide this help display"),
-                    text_section(Color::WHITE, "\nR to reset the simulation"),
-                    text_section(Color::WHITE, "\nEsc to quit"),
-                    text_section(
-                        Color::WHITE,
-                        "\nShift to increase speed (of any other control)",
-                    ),
-                    text_section(
-                        Color::WHITE,
-                        "\nAlt to decrease speed (of any other control)",
-                    ),
-                    TextSection::new(
-                        "\n\nMovement",
-                        TextStyle {
-                            font_size: 60.,
-                            color: Color::WHITE,
-                            ..default()
-                        },
-                    ),
-                    text_section(Color::WHITE, "\nWASD to move laterally"),
-                    text_section(Color::WHITE, "\nMouse to look"),
-                    TextSection::new(
-                        "\n\nSpawning",
-                        TextStyle {
-                            font_size: 60.,
-                            color: Color::WHITE,
-                            ..default()
-                        },
-                    ),
-                    text_section(Color::WHITE, "\nF or click middle mouse to spawn a body"),
-                    text_section(
-                        Color::WHITE,
-                        "\nScroll mouse wheel to modify selected spawn option",
-                    ),
-                    text_section(Color::WHITE, "\nLeft click to select spawn speed"),
-                    text_section(Color::WHITE, "\nRight click to select spawn size"),
-                    TextSection::new(
-                        "\n\nTime",
-                        TextStyle {
-                            font_size: 60.,
-                            color: Color::WHITE,
-                            ..default()
-                        },
-                    ),
-                    text_section(Color::WHITE, "\nP to pause time"),
-                    text_section(Color::WHITE, "\nEquals key to increase simulation rate"),
-                    text_section(Color::WHITE, "\nHyphen key to decrease simulation rate"),
-                ])
-                .with_text_justify(JustifyText::Center),
+                TextBundle::from_sections(help_page(0)).with_text_justify(JustifyText::Center),
                 HelpText,
             ));
         });
 }
 
+// H steps through the help pages and then hides it, and shows the first page again after that
 pub fn show_hide_help(
     mut query: Query<&mut Visibility, With<HelpUI>>,
+    mut text: Query<&mut Text, With<HelpText>>,
     keys: Res<ButtonInput<KeyCode>>,
+    mut page: Local<usize>,
 ) {
-    if keys.just_pressed(KeyCode::KeyH) {
-        // show help
-        *query.single_mut() = match *query.single_mut() {
-            Visibility::Hidden => Visibility::Visible,
-            _ => Visibility::Hidden,
-        };
+    if !keys.just_pressed(KeyCode::KeyH) {
+        return;
+    }
+    let mut visibility = query.single_mut();
+    if *visibility == Visibility::Hidden {
+        *visibility = Visibility::Visible;
+        *page = 0;
+    } else if *page + 1 < HELP_PAGES {
+        *page += 1;
+    } else {
+        *visibility = Visibility::Hidden;
+        return;
     }
+    text.single_mut().sections = help_page(*page);
 }
 
 // see bevymark.rs
@@ -158,55 +328,289 @@ pub fn create_osd(mut commands: Commands) {
                     text_section(Color::BLACK, ""),
                     text_section(Color::BLACK, "\nBody spawn size: "),
                     text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nBody spawn mass: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nBody spawn density: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nBodies per shot: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nSpawn preset: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nSpawn pattern: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nPattern spread: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nVelocity dispersion: "),
+                    text_section(Color::BLACK, ""),
                     text_section(Color::BLACK, "\nTime speed: "),
                     text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nSpawn orbit: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nOrbit eccentricity: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nSpawn point: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nSlingshot: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nReference frame: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nPhysics: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nSpawning: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nBody spawn charge: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nUnits: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nMission clock: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, ""),
+                    // only filled in benchmark mode
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, ""),
                 ]),
                 SpawnText,
             ));
         });
 }
 
+// seconds between refreshes of the energy readout
+const ENERGY_REFRESH_INTERVAL: f64 = 0.25;
+
+#[allow(clippy::too_many_arguments)]
 pub fn update_osd(
     mut query: Query<&mut Text, With<SpawnText>>,
     spawn_options: Res<BodySpawningOptions>,
+    presets: Res<SpawnPresets>,
     time: Res<Time<Virtual>>,
+    benchmark: Res<Benchmark>,
+    selected: Query<(), With<Selected>>,
+    slingshot: Res<Slingshot>,
+    frame: Res<ReferenceFrame>,
+    dissipation: Res<Dissipation>,
+    clock: Res<SimulationClock>,
+    bodies: Query<(&Body, &Position, &Velocity, &Spin, Option<&Charge>)>,
+    laws: Res<ForceLaws>,
+    config: Res<Configuration>,
+    real_time: Res<Time<Real>>,
+    mut energy_updated: Local<Option<f64>>,
 ) {
     let mut text = query.single_mut();
-    text.sections[1].value = format!("{0:.2}", spawn_options.speed);
-    text.sections[3].value = format!("{0:.2}", spawn_options.radius);
-    match spawn_options.mode {
-        SpawnSelectionMode::Size => {
-            text.sections[3].style.color = Color::srgb(1., 0., 0.);
-            text.sections[1].style.color = Color::BLACK;
+    let units = config.units;
+    text.sections[1].value = units.speed(spawn_options.speed as f64);
+    text.sections[3].value = units.length(spawn_options.radius as f64);
+    text.sections[5].value = units.mass(spawn_options.mass());
+    text.sections[7].value = units.density(spawn_options.density as f64);
+    text.sections[9].value = format!("{0}", spawn_options.count.round());
+    text.sections[11].value = match presets.current {
+        Some(current) => presets.presets[current].name.clone(),
+        None => "none".into(),
+    };
+    text.sections[13].value = match spawn_options.pattern {
+        SpawnPattern::Line => "line",
+        SpawnPattern::Cluster => "cluster",
+        SpawnPattern::Shell => "shell",
+        SpawnPattern::Ring => "ring",
+    }
+    .into();
+    text.sections[15].value = units.length(spawn_options.spread as f64);
+    text.sections[17].value = units.speed(spawn_options.dispersion as f64);
+    text.sections[23].value = format!("{0:.2}", spawn_options.eccentricity);
+    // the option being scrolled is shown in red
+    let highlight = |mode: SpawnSelectionMode| {
+        if spawn_options.mode == mode {
+            Color::srgb(1., 0., 0.)
+        } else {
+            Color::BLACK
         }
-        SpawnSelectionMode::Speed => {
-            text.sections[1].style.color = Color::srgb(1., 0., 0.);
-            text.sections[3].style.color = Color::BLACK;
+    };
+    text.sections[1].style.color = highlight(SpawnSelectionMode::Speed);
+    text.sections[3].style.color = highlight(SpawnSelectionMode::Size);
+    text.sections[5].style.color = highlight(SpawnSelectionMode::Mass);
+    text.sections[7].style.color = highlight(SpawnSelectionMode::Density);
+    text.sections[9].style.color = highlight(SpawnSelectionMode::Count);
+    text.sections[15].style.color = highlight(SpawnSelectionMode::Spread);
+    text.sections[17].style.color = highlight(SpawnSelectionMode::Dispersion);
+    text.sections[23].style.color = highlight(SpawnSelectionMode::Eccentricity);
+    text.sections[35].style.color = highlight(SpawnSelectionMode::Charge);
+    if time.is_paused() {
+        text.sections[19].value = "paused".into();
+    } else {
+        text.sections[19].value = format!("{0:.2}x", time.relative_speed());
+        if config.time_scale != 1. {
+            text.sections[19].value += &format!(
+                " ({0} per second)",
+                units.duration(time.relative_speed_f64() * config.time_scale)
+            );
         }
-        _ => {
-            text.sections[1].style.color = Color::BLACK;
-            text.sections[3].style.color = Color::BLACK;
+    }
+    if clock.reversed {
+        text.sections[19].value += ", reversed";
+    }
+    let target = if selected.is_empty() {
+        "nearest body"
+    } else {
+        "selected body"
+    };
+    text.sections[21].value = match spawn_options.orbit {
+        OrbitMode::Straight => "straight".into(),
+        OrbitMode::Circular => format!("circular around {target}"),
+        OrbitMode::Eccentric => format!("eccentric around {target}"),
+        OrbitMode::Escape => format!("escape from {target}"),
+    };
+    text.sections[25].value = match spawn_options.pinned_position {
+        Some(_) => "pinned".into(),
+        None => "camera".into(),
+    };
+    let inherit = if slingshot.inherit_velocity {
+        ", inheriting velocity"
+    } else {
+        ""
+    };
+    text.sections[27].value = match (slingshot.active, slingshot.anchor) {
+        (false, _) => "off".into(),
+        (true, None) => format!("ready{inherit}"),
+        (true, Some(_)) => format!("{0}{inherit}", units.speed(slingshot.velocity.length())),
+    };
+    let applied = match recentering_blocker(&config) {
+        _ if !frame.apply_to_state
+            || !matches!(frame.mode, FrameMode::Barycentric | FrameMode::BodyCentered) =>
+        {
+            String::new()
         }
+        Some(reason) => format!(", not applied to state with {reason}"),
+        None => ", applied to state".into(),
     };
-    if time.is_paused() {
-        text.sections[5].value = "paused".into();
+    text.sections[29].value = match frame.mode {
+        FrameMode::Inertial => "inertial".into(),
+        FrameMode::Barycentric => format!("barycentric{applied}"),
+        FrameMode::BodyCentered => format!("body centered{applied}"),
+        FrameMode::CoRotating => "co-rotating".into(),
+    };
+    let mut physics = match config.gravity_law {
+        GravityLaw::Newtonian => "Newtonian".into(),
+        GravityLaw::PowerLaw => format!("1/r^{0:.2} gravity", config.gravity_power),
+        GravityLaw::Yukawa => format!("Yukawa gravity, range {0:.2}", config.yukawa_length),
+        GravityLaw::Mond => format!("MOND, a0 {0:.2}", config.mond_acceleration),
+    };
+    // the relativistic corrections only apply on top of Newtonian gravity
+    let off = if config.gravity_law == GravityLaw::Newtonian {
+        ""
+    } else {
+        " (off, needs Newtonian gravity)"
+    };
+    if config.post_newtonian {
+        physics += &format!(" + 1PN{off}");
+    }
+    if config.radiation_reaction {
+        physics += &format!(" + 2.5PN radiation reaction{off}");
+    }
+    if config.tidal_disruption {
+        physics += " + tidal disruption";
+    }
+    let medium = if config.drag_atmosphere {
+        "in atmospheres"
+    } else {
+        "everywhere"
+    };
+    match config.drag {
+        Drag::None => {}
+        Drag::Linear => physics += &format!(" + linear drag {medium}"),
+        Drag::Quadratic => physics += &format!(" + quadratic drag {medium}"),
+    }
+    physics += match config.boundary {
+        Boundary::None => "",
+        Boundary::Reflecting => ", reflecting box",
+        Boundary::Periodic => ", periodic box",
+        Boundary::Escape => ", escape sphere",
+    };
+    match config.external_potentials.len() {
+        0 => {}
+        1 => physics += " + 1 external field",
+        n => physics += &format!(" + {n} external fields"),
+    }
+    text.sections[31].value = physics;
+    text.sections[33].value = if spawn_options.test_particles {
+        "test particles".into()
     } else {
-        text.sections[5].value = format!("{0:.2}x", time.relative_speed());
+        "bodies".into()
+    };
+    text.sections[35].value = format!("{0:.2}", spawn_options.charge);
+    text.sections[37].value = format!("{0}, G = {1:.4e}", units.name(), config.gravity_constant);
+    text.sections[39].value = units.duration(clock.elapsed);
+    // the energy is a pass over every pair, so it's only refreshed a few times a second and only while shown
+    let now = real_time.elapsed_seconds_f64();
+    if !config.show_energy {
+        text.sections[40].value.clear();
+        text.sections[41].value.clear();
+        *energy_updated = None;
+    } else if energy_updated.is_none_or(|last| now - last >= ENERGY_REFRESH_INTERVAL) {
+        *energy_updated = Some(now);
+        // test particles carry no energy
+        let states: Vec<BodyState> = bodies
+            .iter()
+            .filter(|(body, ..)| body.mass > 0.)
+            .map(|(body, position, velocity, spin, charge)| {
+                body_state(body, position, velocity, spin, charge)
+            })
+            .collect();
+        text.sections[40].value = "\nEnergy: ".into();
+        // what drag took out is added back, so the total only drifts through integration error
+        text.sections[41].value = match mechanical_energy(&laws, &states, &config) {
+            Some(energy) if dissipation.drag != 0. => format!(
+                "{energy:.4e} + {0:.3e} lost to drag = {1:.4e}",
+                dissipation.drag,
+                energy + dissipation.drag
+            ),
+            Some(energy) => format!("{energy:.4e}"),
+            None => "not defined under these forces".into(),
+        };
+    }
+    if config.benchmark {
+        text.sections[42].value = "\nPair interactions/s (kernel, query loop): ".into();
+        text.sections[43].value = format!(
+            "{0:.3e}, {1:.3e}",
+            benchmark.kernel_rate, benchmark.query_loop_rate
+        );
     }
 }
 
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
 pub fn reset_bodies(
     keys: Res<ButtonInput<KeyCode>>,
-    query: Query<Entity, With<Body>>,
+    query: Query<(Entity, &Body, &Position, &Velocity, &Spin, Option<&Charge>)>,
+    extras: Query<(Option<&Acceleration>, &Orientation, Has<Selected>)>,
+    camera: Query<(&Transform, &CameraPosition), With<Camera>>,
     mut commands: Commands,
     sphere_info: Res<SphereInfo>,
+    mut history: ResMut<EditHistory>,
+    mut dissipation: ResMut<Dissipation>,
+    config: Res<Configuration>,
 ) {
     if keys.just_pressed(KeyCode::KeyR) {
-        let entity_iter = query.iter();
-        for entity in entity_iter {
+        // reset_camera puts the camera back at the same time
+        let (transform, camera_position) = camera.single();
+        let mut edit = WorldEdit {
+            dissipation: Some(dissipation.drag),
+            camera: Some((camera_position.0, *transform)),
+            ..default()
+        };
+        *dissipation = Dissipation::default();
+        for (entity, body, position, velocity, spin, charge) in &query {
             commands.entity(entity).despawn();
+            edit.removed.push(edited_body(
+                entity,
+                body_state(body, position, velocity, spin, charge),
+                &extras,
+            ));
+        }
+        for state in initial_bodies(config.units) {
+            let entity = spawn_body(&mut commands, state, &sphere_info);
+            edit.added.push(EditedBody::spawned(entity, state));
         }
-        initial_spawn(commands, sphere_info);
+        history.record(edit, config.undo_history_length);
     }
 }
 
@@ -214,13 +618,14 @@ pub fn reset_camera(
     keys: Res<ButtonInput<KeyCode>>,
     query: Query<Entity, With<Camera>>,
     mut commands: Commands,
+    config: Res<Configuration>,
 ) {
     if keys.just_pressed(KeyCode::KeyR) {
         let entity_iter = query.iter();
         for entity in entity_iter {
             commands.entity(entity).despawn();
         }
-        camera_spawn(commands);
+        camera_spawn(commands, config);
     }
 }
 
@@ -241,8 +646,14 @@ pub fn capture_or_release_cursor(
 pub fn rotate_camera(
     mut mouse_motion: EventReader<MouseMotion>,
     mut camera: Query<&mut Transform, With<Camera>>,
+    slingshot: Res<Slingshot>,
     config: Res<Configuration>,
 ) {
+    // the mouse aims the slingshot while it is being dragged
+    if slingshot.anchor.is_some() {
+        mouse_motion.clear();
+        return;
+    }
     let mut transform = camera.single_mut();
     for motion in mouse_motion.read() {
         let yaw = -motion.delta.x * config.mouse_sensitivity;
@@ -256,13 +667,45 @@ pub fn spawn_mode_selection(
     buttons: Res<ButtonInput<MouseButton>>,
     keys: Res<ButtonInput<KeyCode>>,
     mut spawn_options: ResMut<BodySpawningOptions>,
+    slingshot: Res<Slingshot>,
 ) {
-    if buttons.just_pressed(MouseButton::Left) {
+    // left click belongs to the slingshot while it is active
+    if buttons.just_pressed(MouseButton::Left) && !slingshot.active {
         spawn_options.mode = SpawnSelectionMode::Speed;
     }
     if buttons.just_pressed(MouseButton::Right) {
         spawn_options.mode = SpawnSelectionMode::Size;
     }
+    if keys.just_pressed(KeyCode::KeyM) {
+        spawn_options.mode = SpawnSelectionMode::Mass;
+    }
+    if keys.just_pressed(KeyCode::KeyN) {
+        spawn_options.mode = SpawnSelectionMode::Density;
+    }
+    if keys.just_pressed(KeyCode::KeyC) {
+        spawn_options.mode = SpawnSelectionMode::Count;
+    }
+    if keys.just_pressed(KeyCode::KeyU) {
+        spawn_options.mode = SpawnSelectionMode::Spread;
+    }
+    if keys.just_pressed(KeyCode::KeyJ) {
+        spawn_options.mode = SpawnSelectionMode::Dispersion;
+    }
+    if keys.just_pressed(KeyCode::KeyL) {
+        spawn_options.pattern = spawn_options.pattern.next();
+    }
+    if keys.just_pressed(KeyCode::KeyE) {
+        spawn_options.mode = SpawnSelectionMode::Eccentricity;
+    }
+    if keys.just_pressed(KeyCode::KeyO) {
+        spawn_options.orbit = spawn_options.orbit.next();
+    }
+    if keys.just_pressed(KeyCode::Semicolon) {
+        spawn_options.mode = SpawnSelectionMode::Charge;
+    }
+    if keys.just_pressed(KeyCode::Backquote) {
+        spawn_options.test_particles = !spawn_options.test_particles;
+    }
     if buttons.just_pressed(MouseButton::Middle) || keys.just_pressed(KeyCode::KeyF) {
         spawn_options.mode = SpawnSelectionMode::Fire;
     }
@@ -272,8 +715,14 @@ pub fn spawn_scrolling(
     mut spawn_options: ResMut<BodySpawningOptions>,
     mut evr_scroll: EventReader<MouseWheel>,
     keys: Res<ButtonInput<KeyCode>>,
+    slingshot: Res<Slingshot>,
     config: Res<Configuration>,
 ) {
+    // scrolling sets the slingshot's depth while it is being dragged
+    if slingshot.anchor.is_some() {
+        evr_scroll.clear();
+        return;
+    }
     // shift lets you control more coarsely
     let mut sens_mod = if keys.pressed(KeyCode::ShiftLeft) {
         config.speed_mod_factor
@@ -293,6 +742,37 @@ pub fn spawn_scrolling(
             SpawnSelectionMode::Speed => {
                 spawn_options.speed += ev.y * config.spawn_speed_mousewheel_sensitivity * sens_mod
             }
+            SpawnSelectionMode::Mass => {
+                // keep the density and grow or shrink the body to fit the new mass
+                let mass = (spawn_options.mass()
+                    + (ev.y * config.spawn_mass_mousewheel_sensitivity * sens_mod) as f64)
+                    .max(0.);
+                spawn_options.radius = get_radius(Body {
+                    mass,
+                    density: spawn_options.density as f64,
+                }) as f32;
+            }
+            SpawnSelectionMode::Density => {
+                spawn_options.density +=
+                    ev.y * config.spawn_density_mousewheel_sensitivity * sens_mod
+            }
+            SpawnSelectionMode::Count => {
+                spawn_options.count += ev.y * config.spawn_count_mousewheel_sensitivity * sens_mod
+            }
+            SpawnSelectionMode::Spread => {
+                spawn_options.spread += ev.y * config.spawn_spread_mousewheel_sensitivity * sens_mod
+            }
+            SpawnSelectionMode::Dispersion => {
+                spawn_options.dispersion +=
+                    ev.y * config.spawn_dispersion_mousewheel_sensitivity * sens_mod
+            }
+            SpawnSelectionMode::Eccentricity => {
+                spawn_options.eccentricity +=
+                    ev.y * config.spawn_eccentricity_mousewheel_sensitivity * sens_mod
+            }
+            SpawnSelectionMode::Charge => {
+                spawn_options.charge += ev.y * config.spawn_charge_mousewheel_sensitivity * sens_mod
+            }
             SpawnSelectionMode::Fire => continue,
         }
     }
@@ -301,243 +781,2240 @@ pub fn spawn_scrolling(
         config.spawn_size_max,
     );
     spawn_options.speed = spawn_options.speed.clamp(0., config.spawn_speed_max);
+    spawn_options.density = spawn_options.density.clamp(
+        config.spawn_density_mousewheel_sensitivity / config.speed_mod_factor,
+        config.spawn_density_max,
+    );
+    spawn_options.count = spawn_options.count.clamp(1., config.spawn_count_max);
+    spawn_options.spread = spawn_options.spread.clamp(0., config.spawn_spread_max);
+    spawn_options.dispersion = spawn_options
+        .dispersion
+        .clamp(0., config.spawn_dispersion_max);
+    spawn_options.eccentricity = spawn_options
+        .eccentricity
+        .clamp(0., config.spawn_eccentricity_max);
+    spawn_options.charge = spawn_options
+        .charge
+        .clamp(-config.spawn_charge_max, config.spawn_charge_max);
+}
+
+// velocity for a body spawned at position: an orbit around the reference body if an orbit mode is chosen,
+// otherwise straight ahead at the spawn speed relative to the reference frame, plus a random spread of up to the velocity dispersion
+#[allow(clippy::too_many_arguments)]
+fn spawn_velocity<'a>(
+    spawn_options: &BodySpawningOptions,
+    frame: &ReferenceFrame,
+    body: Body,
+    position: DVec3,
+    forward: DVec3,
+    bodies: impl Iterator<Item = (&'a Body, &'a Position, &'a Velocity, bool)>,
+    config: &Configuration,
+    rng: &mut Rng,
+) -> DVec3 {
+    let eccentricity = match spawn_options.orbit {
+        OrbitMode::Straight => None,
+        OrbitMode::Circular => Some(0.),
+        OrbitMode::Eccentric => Some(spawn_options.eccentricity as f64),
+        OrbitMode::Escape => Some(1.),
+    };
+    let velocity = match (eccentricity, reference_body(bodies, position)) {
+        (Some(eccentricity), Some((target, target_position, target_velocity, _))) => {
+            orbit_velocity(
+                position,
+                forward,
+                target_position.0,
+                target_velocity.0,
+                circular_speed(
+                    target.mass + body.mass,
+                    position - target_position.0,
+                    config,
+                ),
+                eccentricity,
+            )
+        }
+        // nothing to orbit, just fire it forward
+        _ => frame.velocity_at(position) + forward * spawn_options.speed as f64,
+    };
+    velocity + rng.in_unit_ball() * spawn_options.dispersion as f64
+}
+
+// the body the spawn options describe, test particles are bodies without mass
+fn spawn_body_type(spawn_options: &BodySpawningOptions) -> Body {
+    Body {
+        mass: if spawn_options.test_particles {
+            0.
+        } else {
+            spawn_options.mass()
+        },
+        density: spawn_options.density as f64,
+    }
+}
+
+// where the spawn pattern is centered: the pinned spawn point, or far enough in front of the camera to clear it
+fn spawn_center(
+    spawn_options: &BodySpawningOptions,
+    tf: &Transform,
+    camera_position: &CameraPosition,
+) -> DVec3 {
+    let extent = match spawn_options.pattern {
+        SpawnPattern::Line => 0.,
+        _ => spawn_options.spread as f64,
+    };
+    spawn_options.pinned_position.unwrap_or(
+        camera_position.0 + tf.forward().as_dvec3() * (spawn_options.radius as f64 + 1.01 + extent),
+    )
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn spawn(
-    camera: Query<&Transform, With<Camera>>,
+    camera: Query<(&Transform, &CameraPosition), With<Camera>>,
+    bodies: Query<(&Body, &Position, &Velocity, Has<Selected>)>,
     mut commands: Commands,
     sphere_info: Res<SphereInfo>,
     mut spawn_options: ResMut<BodySpawningOptions>,
+    mut history: ResMut<EditHistory>,
+    mut rng: Local<Rng>,
+    frame: Res<ReferenceFrame>,
+    config: Res<Configuration>,
 ) {
     // check if we need to spawn
     if spawn_options.mode == SpawnSelectionMode::Fire {
         spawn_options.mode = SpawnSelectionMode::None;
-        let tf = camera.single();
+        let (tf, camera_position) = camera.single();
         if spawn_options.radius <= 0. {
             return;
         }
-        let mass = get_mass(spawn_options.radius);
-        commands.spawn(body_bundle(
-            mass,
-            Vec3 {
-                x: tf.translation.x,
-                y: tf.translation.y,
-                z: tf.translation.z,
-            } + Vec3::from(tf.forward()) * (spawn_options.radius + 1.01), // move it in front of the camera
-            tf.forward() * spawn_options.speed,
-            &sphere_info,
-        ));
+        let body = spawn_body_type(&spawn_options);
+        let forward = tf.forward().as_dvec3();
+        let center = spawn_center(&spawn_options, tf, camera_position);
+        let count = spawn_options.count.round() as usize;
+        // space a line out enough that its bodies don't start out touching and merge straight away
+        let spread = match spawn_options.pattern {
+            SpawnPattern::Line => (spawn_options.spread as f64)
+                .max(2.02 * get_radius(body) * count.saturating_sub(1) as f64),
+            _ => spawn_options.spread as f64,
+        };
+        let offsets = pattern_offsets(
+            spawn_options.pattern,
+            count,
+            spread,
+            forward,
+            tf.right().as_dvec3(),
+            &mut rng,
+        );
+        let mut edit = WorldEdit::default();
+        for offset in offsets {
+            let position = center + offset;
+            let state = BodyState {
+                body,
+                position,
+                velocity: spawn_velocity(
+                    &spawn_options,
+                    &frame,
+                    body,
+                    position,
+                    forward,
+                    bodies.iter(),
+                    &config,
+                    &mut rng,
+                ),
+                spin: DVec3::ZERO,
+                charge: spawn_options.charge as f64,
+            };
+            let entity = spawn_body(&mut commands, state, &sphere_info);
+            edit.added.push(EditedBody::spawned(entity, state));
+        }
+        history.record(edit, config.undo_history_length);
     }
 }
 
-pub fn move_camera(
+// while B is held, emit single bodies from the spawn point at the configured rate
+// a body is held back until the last one has cleared the spawn point, so they don't merge as they come out
+// the whole stream is one edit for undo
+#[allow(clippy::too_many_arguments)]
+pub fn stream_spawn(
     keys: Res<ButtonInput<KeyCode>>,
-    mut camera: Query<&mut Transform, With<Camera>>,
+    camera: Query<(&Transform, &CameraPosition), With<Camera>>,
+    bodies: Query<(&Body, &Position, &Velocity, Has<Selected>)>,
+    positions: Query<&Position>,
+    mut commands: Commands,
+    sphere_info: Res<SphereInfo>,
+    spawn_options: Res<BodySpawningOptions>,
+    mut history: ResMut<EditHistory>,
+    mut stream: Local<(f64, WorldEdit, Option<Entity>)>,
+    mut rng: Local<Rng>,
+    frame: Res<ReferenceFrame>,
     time: Res<Time<Real>>,
     config: Res<Configuration>,
 ) {
-    // move faster when shift is held
-    let mut speed_mod = if keys.pressed(KeyCode::ShiftLeft) {
-        config.speed_mod_factor
-    } else {
-        1.
-    };
-    if keys.pressed(KeyCode::AltLeft) {
-        speed_mod /= config.speed_mod_factor;
-    }
-    let motion_distance = time.delta_seconds() * config.camera_speed * speed_mod;
-    let mut transform = camera.single_mut();
-    let mut net_translation = Vec3::ZERO;
-    if keys.pressed(KeyCode::KeyW) {
-        net_translation += *transform.forward();
-    }
-    if keys.pressed(KeyCode::KeyS) {
-        net_translation += *transform.back();
+    let (pending, edit, last) = &mut *stream;
+    if keys.just_released(KeyCode::KeyB) && !edit.added.is_empty() {
+        history.record(std::mem::take(edit), config.undo_history_length);
     }
-    if keys.pressed(KeyCode::KeyA) {
-        net_translation += *transform.left();
+    if !keys.pressed(KeyCode::KeyB) || spawn_options.radius <= 0. {
+        *pending = 0.;
+        return;
     }
-    if keys.pressed(KeyCode::KeyD) {
-        net_translation += *transform.right();
+    // the first body comes out as soon as the key goes down
+    if keys.just_pressed(KeyCode::KeyB) {
+        *pending = 1.;
+    } else {
+        *pending += time.delta_seconds_f64() * config.stream_rate;
     }
-    if keys.pressed(KeyCode::Space) {
-        net_translation += *transform.up();
+    let (tf, camera_position) = camera.single();
+    let body = spawn_body_type(&spawn_options);
+    let forward = tf.forward().as_dvec3();
+    let position = spawn_options.pinned_position.unwrap_or(
+        camera_position.0 + forward * (spawn_options.radius as f64 + 1.01), // move it in front of the camera
+    );
+    let clear = last.is_none_or(|last| {
+        positions.get(last).map_or(true, |last| {
+            last.0.distance(position) >= 2. * get_radius(body)
+        })
+    });
+    // a blocked stream doesn't save up bodies to release all at once
+    if !clear {
+        *pending = pending.min(1.);
+        return;
     }
-    if keys.pressed(KeyCode::ControlLeft) || keys.pressed(KeyCode::ControlRight) {
-        net_translation += *transform.down();
+    if *pending >= 1. {
+        *pending -= 1.;
+        let state = BodyState {
+            body,
+            position,
+            velocity: spawn_velocity(
+                &spawn_options,
+                &frame,
+                body,
+                position,
+                forward,
+                bodies.iter(),
+                &config,
+                &mut rng,
+            ),
+            spin: DVec3::ZERO,
+            charge: spawn_options.charge as f64,
+        };
+        let entity = spawn_body(&mut commands, state, &sphere_info);
+        edit.added.push(EditedBody::spawned(entity, state));
+        *last = Some(entity);
     }
-    transform.translation += net_translation.normalize_or_zero() * motion_distance;
 }
 
-pub fn modify_time(
+// Q applies the next spawn preset and K saves the current spawn options as a new one
+pub fn spawn_presets(
     keys: Res<ButtonInput<KeyCode>>,
-    mut time: ResMut<Time<Virtual>>,
+    mut spawn_options: ResMut<BodySpawningOptions>,
+    mut presets: ResMut<SpawnPresets>,
     config: Res<Configuration>,
 ) {
-    // shift lets you control more coarsely
-    let mut sens_mod = if keys.pressed(KeyCode::ShiftLeft) {
-        config.speed_mod_factor
-    } else {
-        1.
-    };
-
-    // alt lets you control more finely
-    if keys.pressed(KeyCode::AltLeft) {
-        sens_mod /= config.speed_mod_factor;
+    if keys.just_pressed(KeyCode::KeyQ) && !presets.presets.is_empty() {
+        let next = presets
+            .current
+            .map_or(0, |current| (current + 1) % presets.presets.len());
+        spawn_options.apply_preset(presets.presets[next].clone(), config.units);
+        presets.current = Some(next);
     }
-    if keys.just_pressed(KeyCode::KeyP) {
-        if time.is_paused() {
-            time.unpause();
-        } else {
-            time.pause();
-        }
+    if keys.just_pressed(KeyCode::KeyK) {
+        presets.presets.push(spawn_options.preset(config.units));
+        presets.current = Some(presets.presets.len() - 1);
+        presets.save();
     }
-    let mut rate = time.relative_speed();
-    if keys.just_pressed(KeyCode::Equal) {
-        rate += config.time_rate_sensitivity * sens_mod;
+}
+
+// the body a new body at position is spawned relative to: the selected body if there is one, otherwise the nearest
+fn reference_body<'a>(
+    bodies: impl Iterator<Item = (&'a Body, &'a Position, &'a Velocity, bool)>,
+    position: DVec3,
+) -> Option<(&'a Body, &'a Position, &'a Velocity, bool)> {
+    // test particles have nothing to orbit
+    bodies
+        .filter(|(b, p, _, _)| b.mass > 0. && p.0 != position)
+        .min_by(|(_, p1, _, selected1), (_, p2, _, selected2)| {
+            selected2.cmp(selected1).then(
+                p1.0.distance_squared(position)
+                    .total_cmp(&p2.0.distance_squared(position)),
+            )
+        })
+}
+
+// toggle the slingshot tool, and whether slingshot bodies inherit the reference body's velocity
+pub fn toggle_slingshot(keys: Res<ButtonInput<KeyCode>>, mut slingshot: ResMut<Slingshot>) {
+    if keys.just_pressed(KeyCode::KeyV) {
+        slingshot.active = !slingshot.active;
+        slingshot.anchor = None;
     }
-    if keys.just_pressed(KeyCode::Minus) {
-        rate -= config.time_rate_sensitivity * sens_mod;
+    if keys.just_pressed(KeyCode::KeyI) {
+        slingshot.inherit_velocity = !slingshot.inherit_velocity;
     }
-    rate = rate.clamp(config.time_rate_sensitivity / config.speed_mod_factor, 10.);
-    time.set_relative_speed(rate);
 }
 
-pub fn exit_system(keys: Res<ButtonInput<KeyCode>>, mut exit: EventWriter<AppExit>) {
-    if keys.just_pressed(KeyCode::Escape) {
-        exit.send(AppExit::Success);
+// with the slingshot active, left click places a body in front of the camera (or at the pinned spawn point),
+// moving the mouse while holding drags out its velocity in the view plane and scrolling pushes it along the view direction,
+// and releasing fires it
+#[allow(clippy::too_many_arguments)]
+pub fn slingshot(
+    buttons: Res<ButtonInput<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut evr_scroll: EventReader<MouseWheel>,
+    camera: Query<(&Transform, &CameraPosition), With<Camera>>,
+    bodies: Query<(&Body, &Position, &Velocity, Has<Selected>)>,
+    mut commands: Commands,
+    sphere_info: Res<SphereInfo>,
+    spawn_options: Res<BodySpawningOptions>,
+    mut slingshot: ResMut<Slingshot>,
+    mut history: ResMut<EditHistory>,
+    config: Res<Configuration>,
+) {
+    if !slingshot.active {
+        return;
+    }
+    let (tf, camera_position) = camera.single();
+    if buttons.just_pressed(MouseButton::Left) {
+        let radius = spawn_options.radius as f64;
+        slingshot.anchor = Some(spawn_options.pinned_position.unwrap_or(
+            camera_position.0 + tf.forward().as_dvec3() * (radius + config.slingshot_distance),
+        ));
+        slingshot.velocity = DVec3::ZERO;
+    }
+    let Some(anchor) = slingshot.anchor else {
+        return;
+    };
+    for motion in mouse_motion.read() {
+        slingshot.velocity += (tf.right() * motion.delta.x - tf.up() * motion.delta.y).as_dvec3()
+            * config.slingshot_drag_sensitivity as f64;
+    }
+    for ev in evr_scroll.read() {
+        slingshot.velocity +=
+            tf.forward().as_dvec3() * (ev.y * config.slingshot_scroll_sensitivity) as f64;
+    }
+    if buttons.just_released(MouseButton::Left) {
+        slingshot.anchor = None;
+        let inherited = if slingshot.inherit_velocity {
+            reference_body(bodies.iter(), anchor)
+                .map(|(_, _, velocity, _)| velocity.0)
+                .unwrap_or_default()
+        } else {
+            DVec3::ZERO
+        };
+        let state = BodyState {
+            body: spawn_body_type(&spawn_options),
+            position: anchor,
+            velocity: slingshot.velocity + inherited,
+            spin: DVec3::ZERO,
+            charge: spawn_options.charge as f64,
+        };
+        let entity = spawn_body(&mut commands, state, &sphere_info);
+        history.record(
+            WorldEdit {
+                removed: Vec::new(),
+                added: vec![EditedBody::spawned(entity, state)],
+                ..default()
+            },
+            config.undo_history_length,
+        );
     }
 }
 
-pub fn create_sphere_info(
-    mut sphere_info: ResMut<SphereInfo>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut images: ResMut<Assets<Image>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+// show the body being aimed and an arrow to where it would be after one second at its launch velocity
+pub fn draw_slingshot(
+    mut gizmos: Gizmos,
+    camera: Query<&CameraPosition>,
+    slingshot: Res<Slingshot>,
+    spawn_options: Res<BodySpawningOptions>,
 ) {
-    let mesh_handle = meshes.add(Sphere::default().mesh().uv(32, 18));
-    let material_handle = materials.add(StandardMaterial {
-        base_color_texture: Some(images.add(uv_debug_texture())),
-        ..default()
-    });
-    sphere_info.0 = mesh_handle;
-    sphere_info.1 = material_handle;
+    let Some(anchor) = slingshot.anchor else {
+        return;
+    };
+    let start = (anchor - camera.single().0).as_vec3();
+    let color = Color::srgb(1., 0.85, 0.4);
+    gizmos.sphere(start, Quat::IDENTITY, spawn_options.radius, color);
+    gizmos.arrow(start, start + slingshot.velocity.as_vec3(), color);
 }
 
-pub fn initial_spawn(mut commands: Commands, sphere_info: Res<SphereInfo>) {
-    commands.spawn(body_bundle(
-        1.,
-        Vec3 {
-            x: 0.,
-            y: 0.,
-            z: 2.,
-        },
-        Vec3 {
-            x: 0.,
-            y: 1.,
-            z: 0.,
-        },
-        &sphere_info,
-    ));
-    commands.spawn(body_bundle(
-        1.,
-        Vec3 {
-            x: 0.,
-            y: 0.,
-            z: -2.,
-        },
-        Vec3 {
-            x: 0.,
-            y: -1.,
-            z: 0.,
-        },
-        &sphere_info,
-    ));
+// pin the spawn point in front of the camera, or unpin it so bodies spawn from the camera again
+pub fn pin_spawn_point(
+    keys: Res<ButtonInput<KeyCode>>,
+    camera: Query<(&Transform, &CameraPosition), With<Camera>>,
+    mut spawn_options: ResMut<BodySpawningOptions>,
+) {
+    if keys.just_pressed(KeyCode::KeyG) {
+        let (tf, camera_position) = camera.single();
+        spawn_options.pinned_position = match spawn_options.pinned_position {
+            Some(_) => None,
+            None => Some(
+                camera_position.0 + tf.forward().as_dvec3() * (spawn_options.radius as f64 + 1.01),
+            ),
+        };
+    }
 }
 
-pub fn camera_spawn(mut commands: Commands) {
-    // camera
-    commands.spawn(Camera3dBundle {
-        transform: Transform::from_xyz(-2.5, 4.5, 9.0).looking_at(Vec3::ZERO, Vec3::Y),
-        ..default()
-    });
+// the body in the center of the view that is closest to the camera
+fn body_in_view<'a>(
+    tf: &Transform,
+    camera_position: &CameraPosition,
+    bodies: impl Iterator<Item = (Entity, &'a Body, &'a Position)>,
+) -> Option<Entity> {
+    let forward = tf.forward().as_dvec3();
+    bodies
+        .filter_map(|(entity, body, position)| {
+            ray_sphere_distance(camera_position.0, forward, position.0, get_radius(*body))
+                .map(|distance| (distance, entity))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, entity)| entity)
 }
 
-// sum gravitational forces on bodies to arrive at their acceleration, euler integrate acceleration to modify velocity
-pub fn update_body_velocities(
-    mut query: Query<(&Body, &Position, &mut Velocity)>,
-    time: Res<Time<Virtual>>,
+// select the body in the center of the view as the orbit target, or clear the selection if there is none
+pub fn select_body(
+    keys: Res<ButtonInput<KeyCode>>,
+    camera: Query<(&Transform, &CameraPosition), With<Camera>>,
+    bodies: Query<(Entity, &Body, &Position)>,
+    selected: Query<Entity, With<Selected>>,
+    mut commands: Commands,
+) {
+    if !keys.just_pressed(KeyCode::KeyT) {
+        return;
+    }
+    for entity in &selected {
+        commands.entity(entity).remove::<Selected>();
+    }
+    let (tf, camera_position) = camera.single();
+    if let Some(entity) = body_in_view(tf, camera_position, bodies.iter()) {
+        commands.entity(entity).try_insert(Selected);
+    }
+}
+
+// delete the body in the center of the view
+#[allow(clippy::type_complexity)]
+pub fn delete_body(
+    keys: Res<ButtonInput<KeyCode>>,
+    camera: Query<(&Transform, &CameraPosition), With<Camera>>,
+    query: Query<(Entity, &Body, &Position, &Velocity, &Spin, Option<&Charge>)>,
+    extras: Query<(Option<&Acceleration>, &Orientation, Has<Selected>)>,
+    mut commands: Commands,
+    mut history: ResMut<EditHistory>,
+    config: Res<Configuration>,
+) {
+    if !keys.just_pressed(KeyCode::KeyX) {
+        return;
+    }
+    let (tf, camera_position) = camera.single();
+    let in_view = body_in_view(
+        tf,
+        camera_position,
+        query
+            .iter()
+            .map(|(entity, body, position, _, _, _)| (entity, body, position)),
+    );
+    if let Some((entity, body, position, velocity, spin, charge)) =
+        in_view.and_then(|e| query.get(e).ok())
+    {
+        commands.entity(entity).despawn();
+        history.record(
+            WorldEdit {
+                removed: vec![edited_body(
+                    entity,
+                    body_state(body, position, velocity, spin, charge),
+                    &extras,
+                )],
+                added: Vec::new(),
+                ..default()
+            },
+            config.undo_history_length,
+        );
+    }
+}
+
+fn body_state(
+    body: &Body,
+    position: &Position,
+    velocity: &Velocity,
+    spin: &Spin,
+    charge: Option<&Charge>,
+) -> BodyState {
+    BodyState {
+        body: *body,
+        position: position.0,
+        velocity: velocity.0,
+        spin: spin.0,
+        charge: charge.map_or(0., |charge| charge.0),
+    }
+}
+
+// a body about to be removed by an edit, with its step, turn and selection
+fn edited_body(
+    entity: Entity,
+    state: BodyState,
+    extras: &Query<(Option<&Acceleration>, &Orientation, Has<Selected>)>,
+) -> EditedBody {
+    let (acceleration, orientation, selected) = extras.get(entity).map_or(
+        (None, DQuat::IDENTITY, false),
+        |(acceleration, orientation, selected)| {
+            (
+                acceleration.map(|acceleration| acceleration.0),
+                orientation.0,
+                selected,
+            )
+        },
+    );
+    EditedBody {
+        entity,
+        state,
+        acceleration,
+        orientation,
+        selected,
+    }
+}
+
+// despawn one side of an edit and respawn the other exactly as recorded
+fn swap_bodies(
+    commands: &mut Commands,
+    sphere_info: &Res<SphereInfo>,
+    history: &mut EditHistory,
+    rewind: &mut Rewind,
+    selected: &Query<Entity, With<Selected>>,
+    despawn: &[EditedBody],
+    respawn: &mut [EditedBody],
+) {
+    // a body that comes back selected takes the selection from whichever body has it now
+    if respawn.iter().any(|body| body.selected) {
+        for entity in selected {
+            commands.entity(entity).remove::<Selected>();
+        }
+    }
+    for body in despawn {
+        // the body may have been absorbed in a collision since
+        if let Some(mut entity_commands) = commands.get_entity(body.entity) {
+            entity_commands.despawn();
+        }
+    }
+    for body in respawn {
+        let new = spawn_body(commands, body.state, sphere_info);
+        let mut entity_commands = commands.entity(new);
+        entity_commands.insert(Orientation(body.orientation));
+        if let Some(acceleration) = body.acceleration {
+            entity_commands.insert(Acceleration(acceleration));
+        }
+        if body.selected {
+            entity_commands.insert(Selected);
+        }
+        history.remap(body.entity, new);
+        rewind.remap(body.entity, new);
+        body.entity = new;
+    }
+}
+
+// whether the bodies an edit would despawn are all still there as recorded
+// one that has merged, broken up or escaped since can't be taken back without losing or duplicating mass
+fn unchanged(bodies: &Query<&Body>, despawn: &[EditedBody]) -> bool {
+    despawn.iter().all(|edited| {
+        bodies
+            .get(edited.entity)
+            .is_ok_and(|body| body.mass == edited.state.body.mass)
+    })
+}
+
+// a reset's camera is swapped with the current one, so undo brings back the view from before it and redo the start
+fn swap_camera(
+    camera: &mut Query<(&mut Transform, &mut CameraPosition), With<Camera>>,
+    recorded: &mut Option<(DVec3, Transform)>,
+) {
+    let Some((position, transform)) = recorded else {
+        return;
+    };
+    if let Ok((mut current_transform, mut current_position)) = camera.get_single_mut() {
+        std::mem::swap(position, &mut current_position.0);
+        std::mem::swap(transform, &mut *current_transform);
+    }
+}
+
+// ctrl+z undoes the last world edit and ctrl+y redoes it
+#[allow(clippy::too_many_arguments)]
+pub fn undo_redo(
+    keys: Res<ButtonInput<KeyCode>>,
+    bodies: Query<&Body>,
+    selected: Query<Entity, With<Selected>>,
+    mut commands: Commands,
+    sphere_info: Res<SphereInfo>,
+    mut history: ResMut<EditHistory>,
+    mut rewind: ResMut<Rewind>,
+    mut dissipation: ResMut<Dissipation>,
+    mut camera: Query<(&mut Transform, &mut CameraPosition), With<Camera>>,
+) {
+    if !(keys.pressed(KeyCode::ControlLeft) || keys.pressed(KeyCode::ControlRight)) {
+        return;
+    }
+    if keys.just_pressed(KeyCode::KeyZ) {
+        if let Some(mut edit) = history.pop_undo() {
+            if !unchanged(&bodies, &edit.added) {
+                println!("Can't undo, the bodies it would remove have collided, broken up or escaped since");
+                history.push_undo(edit);
+                return;
+            }
+            swap_bodies(
+                &mut commands,
+                &sphere_info,
+                &mut history,
+                &mut rewind,
+                &selected,
+                &edit.added,
+                &mut edit.removed,
+            );
+            if let Some(drag) = &mut edit.dissipation {
+                std::mem::swap(drag, &mut dissipation.drag);
+            }
+            swap_camera(&mut camera, &mut edit.camera);
+            history.push_redo(edit);
+        }
+    }
+    if keys.just_pressed(KeyCode::KeyY) {
+        if let Some(mut edit) = history.pop_redo() {
+            if !unchanged(&bodies, &edit.removed) {
+                println!("Can't redo, the bodies it would remove have collided, broken up or escaped since");
+                history.push_redo(edit);
+                return;
+            }
+            swap_bodies(
+                &mut commands,
+                &sphere_info,
+                &mut history,
+                &mut rewind,
+                &selected,
+                &edit.removed,
+                &mut edit.added,
+            );
+            if let Some(drag) = &mut edit.dissipation {
+                std::mem::swap(drag, &mut dissipation.drag);
+            }
+            swap_camera(&mut camera, &mut edit.camera);
+            history.push_undo(edit);
+        }
+    }
+}
+
+pub fn move_camera(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut camera: Query<(&Transform, &mut CameraPosition), With<Camera>>,
+    mut ctrl_is_modifier: Local<bool>,
+    time: Res<Time<Real>>,
+    config: Res<Configuration>,
+) {
+    // move faster when shift is held
+    let mut speed_mod = if keys.pressed(KeyCode::ShiftLeft) {
+        config.speed_mod_factor
+    } else {
+        1.
+    };
+    if keys.pressed(KeyCode::AltLeft) {
+        speed_mod /= config.speed_mod_factor;
+    }
+    let motion_distance = time.delta_seconds() * config.camera_speed * speed_mod;
+    let (transform, mut camera_position) = camera.single_mut();
+    let mut net_translation = Vec3::ZERO;
+    if keys.pressed(KeyCode::KeyW) {
+        net_translation += *transform.forward();
+    }
+    if keys.pressed(KeyCode::KeyS) {
+        net_translation += *transform.back();
+    }
+    if keys.pressed(KeyCode::KeyA) {
+        net_translation += *transform.left();
+    }
+    if keys.pressed(KeyCode::KeyD) {
+        net_translation += *transform.right();
+    }
+    if keys.pressed(KeyCode::Space) {
+        net_translation += *transform.up();
+    }
+    // ctrl only moves down until it is used for undo or redo, and then not again until it is released
+    let ctrl = keys.pressed(KeyCode::ControlLeft) || keys.pressed(KeyCode::ControlRight);
+    if !ctrl {
+        *ctrl_is_modifier = false;
+    } else if keys.pressed(KeyCode::KeyZ) || keys.pressed(KeyCode::KeyY) {
+        *ctrl_is_modifier = true;
+    }
+    if ctrl && !*ctrl_is_modifier {
+        net_translation += *transform.down();
+    }
+    // the camera's transform stays at the render origin, only its simulation position moves
+    camera_position.0 += (net_translation.normalize_or_zero() * motion_distance).as_dvec3();
+}
+
+pub fn modify_time(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut time: ResMut<Time<Virtual>>,
+    mut clock: ResMut<SimulationClock>,
+    config: Res<Configuration>,
+) {
+    // shift lets you control more coarsely
+    let mut sens_mod = if keys.pressed(KeyCode::ShiftLeft) {
+        config.speed_mod_factor
+    } else {
+        1.
+    };
+
+    // alt lets you control more finely
+    if keys.pressed(KeyCode::AltLeft) {
+        sens_mod /= config.speed_mod_factor;
+    }
+    if keys.just_pressed(KeyCode::KeyP) {
+        if time.is_paused() {
+            time.unpause();
+        } else {
+            time.pause();
+        }
+    }
+    if keys.just_pressed(KeyCode::Period) {
+        time.pause();
+        clock.single_step = true;
+    }
+    if keys.just_pressed(KeyCode::Comma) {
+        clock.reversed = !clock.reversed;
+    }
+    let mut rate = time.relative_speed();
+    if keys.just_pressed(KeyCode::Equal) {
+        rate += config.time_rate_sensitivity * sens_mod;
+    }
+    if keys.just_pressed(KeyCode::Minus) {
+        rate -= config.time_rate_sensitivity * sens_mod;
+    }
+    rate = rate.clamp(config.time_rate_sensitivity / config.speed_mod_factor, 10.);
+    time.set_relative_speed(rate);
+}
+
+pub fn exit_system(keys: Res<ButtonInput<KeyCode>>, mut exit: EventWriter<AppExit>) {
+    if keys.just_pressed(KeyCode::Escape) {
+        exit.send(AppExit::Success);
+    }
+}
+
+pub fn create_sphere_info(
+    mut sphere_info: ResMut<SphereInfo>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mesh_handle = meshes.add(Sphere::default().mesh().uv(32, 18));
+    let material_handle = materials.add(StandardMaterial {
+        base_color_texture: Some(images.add(uv_debug_texture())),
+        ..default()
+    });
+    sphere_info.0 = mesh_handle;
+    sphere_info.1 = material_handle;
+}
+
+// the bodies the simulation starts (and resets) with
+// the built-in scene, scaled to the unit system
+fn initial_bodies(units: UnitSystem) -> [BodyState; 2] {
+    let (length, mass, time) = units.scene();
+    [
+        BodyState {
+            body: Body {
+                mass: 1.,
+                density: 1.,
+            },
+            position: DVec3 {
+                x: 0.,
+                y: 0.,
+                z: 2.,
+            },
+            velocity: DVec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            spin: DVec3::ZERO,
+            charge: 0.,
+        },
+        BodyState {
+            body: Body {
+                mass: 1.,
+                density: 1.,
+            },
+            position: DVec3 {
+                x: 0.,
+                y: 0.,
+                z: -2.,
+            },
+            velocity: DVec3 {
+                x: 0.,
+                y: -1.,
+                z: 0.,
+            },
+            spin: DVec3::ZERO,
+            charge: 0.,
+        },
+    ]
+    .map(|state| BodyState {
+        body: Body {
+            mass: state.body.mass * mass,
+            density: state.body.density * mass / length.powi(3),
+        },
+        position: state.position * length,
+        velocity: state.velocity * length / time,
+        ..state
+    })
+}
+
+pub fn initial_spawn(
+    mut commands: Commands,
+    sphere_info: Res<SphereInfo>,
     config: Res<Configuration>,
 ) {
-    let dt = time.delta_seconds();
-    let mut query_next = query.iter_combinations_mut();
-    while let Some([(body1, &p1, mut v1), (body2, &p2, mut v2)]) = query_next.fetch_next() {
-        let m1 = body1.mass;
-        let m2 = body2.mass;
+    for state in initial_bodies(config.units) {
+        spawn_body(&mut commands, state, &sphere_info);
+    }
+}
+
+pub fn camera_spawn(mut commands: Commands, config: Res<Configuration>) {
+    // camera
+    // only the orientation lives in the transform, the position is kept in double precision and used as the floating origin
+    // the view is scaled with the unit system's scene, clipping planes included
+    let (length, ..) = config.units.scene();
+    let eye = Vec3::new(-2.5, 4.5, 9.0);
+    let projection = PerspectiveProjection::default();
+    commands.spawn((
+        Camera3dBundle {
+            transform: Transform::from_translation(eye)
+                .looking_at(Vec3::ZERO, Vec3::Y)
+                .with_translation(Vec3::ZERO),
+            projection: PerspectiveProjection {
+                near: projection.near * length as f32,
+                far: projection.far * length as f32,
+                ..projection
+            }
+            .into(),
+            ..default()
+        },
+        CameraPosition(eye.as_dvec3() * length),
+    ));
+}
+
+// the original pairwise loop over the ECS query, only run in benchmark mode to compare against the kernel
+#[allow(clippy::type_complexity)]
+fn query_pair_loop(
+    query: &Query<
+        (
+            Entity,
+            &Body,
+            &Position,
+            &mut Velocity,
+            Option<&mut Acceleration>,
+            &Spin,
+            Option<&Charge>,
+        ),
+        Without<TestParticle>,
+    >,
+    gravity_constant: f64,
+) {
+    for [(_, body1, p1, ..), (_, body2, p2, ..)] in query.iter_combinations() {
         let r2 = p2.0 - p1.0;
         let r1 = -r2;
-        let dist = r1.norm();
-        let a1 = config.gravity_constant * m2 * r2 / dist.powf(3.);
-        let a2 = config.gravity_constant * m1 * r1 / dist.powf(3.);
-        v1.0 += a1 * dt;
-        v2.0 += a2 * dt;
+        let dist = r1.length();
+        black_box(gravity_constant * body2.mass * r2 / dist.powf(3.));
+        black_box(gravity_constant * body1.mass * r1 / dist.powf(3.));
     }
 }
 
-// euler integrate body velocities to update body positions
-pub fn update_body_positions(
-    mut query: Query<(&mut Position, &Velocity)>,
+// sum the registered forces on bodies to arrive at their acceleration, which finishes the step with a half kick
+// bodies are gathered into a structure of arrays and the force evaluation is spread over the compute task pool
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+pub fn update_body_velocities(
+    mut query: Query<
+        (
+            Entity,
+            &Body,
+            &Position,
+            &mut Velocity,
+            Option<&mut Acceleration>,
+            &Spin,
+            Option<&Charge>,
+        ),
+        Without<TestParticle>,
+    >,
+    mut commands: Commands,
+    mut buffer: Local<BodyBuffer>,
+    laws: Res<ForceLaws>,
+    mut benchmark: ResMut<Benchmark>,
+    clock: Res<SimulationClock>,
+    real_time: Res<Time<Real>>,
+    config: Res<Configuration>,
+) {
+    let dt = clock.dt;
+    buffer.gather(
+        query
+            .iter()
+            .map(|(_, body, position, ..)| (position.0, body.mass)),
+    );
+    buffer.period = config.period();
+    let states: Vec<BodyState> = query
+        .iter()
+        .map(|(_, body, position, velocity, _, spin, charge)| {
+            body_state(body, position, velocity, spin, charge)
+        })
+        .collect();
+    let mut accelerations = vec![DVec3::ZERO; states.len()];
+    for law in &laws.pair {
+        for (acceleration, a) in accelerations
+            .iter_mut()
+            .zip(law.accelerations(&buffer, &states, &config))
+        {
+            *acceleration += a;
+        }
+    }
+    // iteration order is the same as when gathering, so accelerations line up with their bodies
+    for (((entity, _, _, mut velocity, stored, _, _), acceleration), state) in
+        query.iter_mut().zip(accelerations).zip(&states)
+    {
+        let own: DVec3 = laws
+            .body
+            .iter()
+            .map(|law| law.acceleration(state, &config))
+            .sum();
+        finish_kick(
+            entity,
+            &mut velocity,
+            stored,
+            acceleration + own,
+            dt,
+            &mut commands,
+        );
+    }
+
+    if config.benchmark {
+        // the vectorized gravity kernel is timed on its own, the other laws aren't part of the comparison
+        let start = Instant::now();
+        black_box(gravity_accelerations(
+            &buffer,
+            config.gravity_constant,
+            config.force_threads,
+        ));
+        let kernel_seconds = start.elapsed().as_secs_f64();
+        let start = Instant::now();
+        query_pair_loop(&query, config.gravity_constant);
+        let query_loop_seconds = start.elapsed().as_secs_f64();
+        let n = buffer.len() as f64;
+        benchmark.record(
+            n * (n - 1.) / 2.,
+            kernel_seconds,
+            query_loop_seconds,
+            real_time.delta_seconds_f64(),
+        );
+    }
+}
+
+// keep bodies inside the boundary: bounce them off the walls, wrap them around, or remove them once they escape
+pub fn apply_boundary(
+    mut query: Query<(
+        Entity,
+        &Body,
+        &mut Position,
+        &mut PreviousPosition,
+        &mut Velocity,
+    )>,
+    mut commands: Commands,
+    mut escapes: EventWriter<EscapeEvent>,
+    clock: Res<SimulationClock>,
+    config: Res<Configuration>,
+) {
+    let size = config.boundary_size;
+    for (entity, body, mut position, mut previous, mut velocity) in &mut query {
+        match config.boundary {
+            Boundary::None => return,
+            Boundary::Reflecting => {
+                reflect_into_box(&mut position.0, &mut previous.0, &mut velocity.0, size);
+            }
+            Boundary::Periodic => wrap_into_box(&mut position.0, &mut previous.0, size),
+            Boundary::Escape => {
+                if position.0.length() > size {
+                    escapes.send(EscapeEvent {
+                        time: clock.elapsed,
+                        body: entity,
+                        mass: body.mass,
+                        position: position.0,
+                        velocity: velocity.0,
+                    });
+                    commands.entity(entity).despawn();
+                }
+            }
+        }
+    }
+}
+
+// outline the boundary
+pub fn draw_boundary(
+    mut gizmos: Gizmos,
+    camera: Query<&CameraPosition>,
+    config: Res<Configuration>,
+) {
+    let center = -camera.single().0.as_vec3();
+    let size = config.boundary_size as f32;
+    let color = Color::srgb(0.3, 0.5, 0.3);
+    match config.boundary {
+        Boundary::None => {}
+        Boundary::Reflecting | Boundary::Periodic => {
+            gizmos.cuboid(
+                Transform::from_translation(center).with_scale(Vec3::splat(2. * size)),
+                color,
+            );
+        }
+        Boundary::Escape => {
+            gizmos.sphere(center, Quat::IDENTITY, size, color);
+        }
+    }
+}
+
+// turn bodies at their spin rate
+pub fn update_body_orientations(
+    mut query: Query<(&mut Orientation, &Spin)>,
+    clock: Res<SimulationClock>,
+) {
+    let dt = clock.dt;
+    for (mut orientation, spin) in &mut query {
+        orientation.0 = (DQuat::from_scaled_axis(spin.0 * dt) * orientation.0).normalize();
+    }
+}
+
+// accelerate test particles in the field of the massive bodies, which they don't disturb
+#[allow(clippy::type_complexity)]
+pub fn update_particle_velocities(
+    bodies: Query<(&Body, &Position, &Velocity, &Spin, Option<&Charge>), Without<TestParticle>>,
+    mut particles: Query<
+        (
+            Entity,
+            &Body,
+            &Position,
+            &mut Velocity,
+            Option<&mut Acceleration>,
+            &Spin,
+            Option<&Charge>,
+        ),
+        With<TestParticle>,
+    >,
+    mut commands: Commands,
+    mut buffer: Local<BodyBuffer>,
+    laws: Res<ForceLaws>,
+    clock: Res<SimulationClock>,
+    config: Res<Configuration>,
+) {
+    if particles.is_empty() {
+        return;
+    }
+    let dt = clock.dt;
+    buffer.gather(
+        bodies
+            .iter()
+            .map(|(body, position, _, _, _)| (position.0, body.mass)),
+    );
+    buffer.period = config.period();
+    let sources: Vec<BodyState> = bodies
+        .iter()
+        .map(|(body, position, velocity, spin, charge)| {
+            body_state(body, position, velocity, spin, charge)
+        })
+        .collect();
+    let states: Vec<BodyState> = particles
+        .iter()
+        .map(|(_, body, position, velocity, _, spin, charge)| {
+            body_state(body, position, velocity, spin, charge)
+        })
+        .collect();
+    let mut accelerations = vec![DVec3::ZERO; states.len()];
+    for law in &laws.pair {
+        for (acceleration, a) in accelerations
+            .iter_mut()
+            .zip(law.field_accelerations(&buffer, &sources, &states, &config))
+        {
+            *acceleration += a;
+        }
+    }
+    for (((entity, _, _, mut velocity, stored, _, _), acceleration), state) in
+        particles.iter_mut().zip(accelerations).zip(&states)
+    {
+        let own: DVec3 = laws
+            .body
+            .iter()
+            .map(|law| law.acceleration(state, &config))
+            .sum();
+        finish_kick(
+            entity,
+            &mut velocity,
+            stored,
+            acceleration + own,
+            dt,
+            &mut commands,
+        );
+    }
+}
+
+// slow bodies down relative to the medium they move through, which is either at rest everywhere or
+// the atmosphere of the body whose air is thickest where they are
+// the drag is integrated exactly over the step so strong drag can't overshoot, and the lost energy is recorded
+// drag only ever takes energy away, so it is left out while time runs backwards
+pub fn apply_drag(
+    mut query: Query<(Entity, &Body, &Position, &mut Velocity)>,
+    mut dissipation: ResMut<Dissipation>,
+    clock: Res<SimulationClock>,
+    config: Res<Configuration>,
+) {
+    if config.drag == Drag::None {
+        return;
+    }
+    let (entities, bodies): (Vec<Entity>, Vec<(Body, DVec3, DVec3)>) = query
+        .iter()
+        .map(|(entity, body, position, velocity)| (entity, (*body, position.0, velocity.0)))
+        .unzip();
+    let (kicks, dissipated) = drag_kicks(&bodies, clock.dt, &config);
+    dissipation.drag += dissipated;
+    for (index, kick) in kicks {
+        if let Ok((_, _, _, mut velocity)) = query.get_mut(entities[index]) {
+            velocity.0 += kick;
+        }
+    }
+}
+
+// the length of this frame's physics step, from the virtual time unless a single step was asked for while paused
+pub fn advance_clock(
     time: Res<Time<Virtual>>,
+    mut clock: ResMut<SimulationClock>,
+    config: Res<Configuration>,
 ) {
-    let dt = time.delta_seconds();
-    query.iter_mut().for_each(|(mut position, velocity)| {
-        position.0.x += velocity.0.x * dt;
-        position.0.y += velocity.0.y * dt;
-        position.0.z += velocity.0.z * dt;
-    });
+    let dt = if !time.is_paused() {
+        time.delta_seconds_f64() * config.time_scale
+    } else if clock.single_step {
+        config.single_step_size
+    } else {
+        0.
+    };
+    clock.single_step = false;
+    clock.dt = if clock.reversed { -dt } else { dt };
+    clock.elapsed += clock.dt;
+}
+
+// first half of a velocity verlet step: kick velocities by the accelerations from the end of the last step, then drift
+// the velocity systems finish the step with a second half kick using the new accelerations
+// bodies without an acceleration yet just drift, and get the whole kick at the end of the step
+// the step is time symmetric, so a negative length runs it backwards, though frame lengths vary and so
+// running back doesn't land exactly on the states passed going forward
+pub fn update_body_positions(
+    mut query: Query<(
+        &mut Position,
+        &mut PreviousPosition,
+        &mut Velocity,
+        Option<&Acceleration>,
+    )>,
+    clock: Res<SimulationClock>,
+) {
+    let dt = clock.dt;
+    query
+        .iter_mut()
+        .for_each(|(mut position, mut previous, mut velocity, acceleration)| {
+            previous.0 = position.0;
+            if let Some(acceleration) = acceleration {
+                velocity.0 += acceleration.0 * dt / 2.;
+            }
+            position.0 += velocity.0 * dt;
+        });
+}
+
+// the closing kick of a step, the whole kick for a body that had no acceleration to start it with
+fn finish_kick(
+    entity: Entity,
+    velocity: &mut Velocity,
+    stored: Option<Mut<Acceleration>>,
+    acceleration: DVec3,
+    dt: f64,
+    commands: &mut Commands,
+) {
+    match stored {
+        Some(mut stored) => {
+            stored.0 = acceleration;
+            velocity.0 += acceleration * dt / 2.;
+        }
+        None => {
+            commands.entity(entity).insert(Acceleration(acceleration));
+            velocity.0 += acceleration * dt;
+        }
+    }
 }
 
 // combine colliding bodies into one
+// bodies are swept from their previous to their current position so fast bodies can't tunnel through each other
+// candidates come from a spatial hash over the swept volumes, contacts are merged in time order at the moment of contact,
+// and the search is repeated after merging since a merged body can reach new neighbours
+#[allow(clippy::type_complexity)]
 pub fn resolve_body_collisions(
-    mut query: Query<(Entity, &mut Body, &mut Position, &mut Velocity)>,
+    mut query: Query<(
+        Entity,
+        &mut Body,
+        &mut Position,
+        &mut PreviousPosition,
+        &mut Velocity,
+        &mut Spin,
+        Option<&mut Charge>,
+    )>,
     mut commands: Commands,
+    mut collisions: EventWriter<CollisionEvent>,
+    clock: Res<SimulationClock>,
+    config: Res<Configuration>,
 ) {
-    let mut query_next = query.iter_combinations_mut();
-    while let Some([(_entity1, mut body1, mut p1, mut v1), (entity2, mut body2, p2, v2)]) =
-        query_next.fetch_next()
-    {
-        let dist_collision = get_radius(*body1) + get_radius(*body2);
-        let dist_actual = (p2.0 - p1.0).norm();
-
-        // are the bodies colliding?
-        if dist_actual <= dist_collision {
-            // calculate quantities we'll use
-            let m1 = body1.mass;
-            let m2 = body2.mass;
-            // if either entity's mass is 0, skip (this collision doesn't matter)
-            if m1 == 0. || m2 == 0. {
+    let dt = clock.dt;
+    let period = config.period();
+    let mut bodies: Vec<(Entity, Body, DVec3, DVec3, DVec3, DVec3)> = query
+        .iter()
+        .map(|(entity, body, position, previous, velocity, spin, _)| {
+            (entity, *body, previous.0, position.0, velocity.0, spin.0)
+        })
+        .collect();
+    let mut charges: Vec<f64> = query
+        .iter()
+        .map(|(.., charge)| charge.map_or(0., |charge| charge.0))
+        .collect();
+    let mut merged = vec![false; bodies.len()];
+    let mut absorbed = vec![false; bodies.len()];
+    // fraction of the step each body's path is real from, a merged body's path before its merge is made up
+    let mut not_before = vec![0.; bodies.len()];
+    loop {
+        // bound what is left of each swept sphere by a sphere around the middle of its path
+        // absorbed bodies and massless test particles get no radius so the broad phase ignores them
+        let from: Vec<DVec3> = bodies
+            .iter()
+            .zip(&not_before)
+            .map(|(b, &t0)| b.2.lerp(b.3, t0))
+            .collect();
+        let centers: Vec<DVec3> = bodies
+            .iter()
+            .zip(&from)
+            .map(|(b, from)| (*from + b.3) / 2.)
+            .collect();
+        let radii: Vec<f64> = bodies
+            .iter()
+            .zip(&from)
+            .zip(&absorbed)
+            .map(|((b, from), &gone)| {
+                if gone || b.1.mass == 0. {
+                    0.
+                } else {
+                    get_radius(b.1) + (b.3 - *from).length() / 2.
+                }
+            })
+            .collect();
+        let mut contacts: Vec<(f64, usize, usize)> = overlapping_pairs(&centers, &radii, period)
+            .into_iter()
+            .filter_map(|(i, j)| {
+                let (_, body_i, start_i, end_i, _, _) = bodies[i];
+                let (_, body_j, start_j, end_j, _, _) = bodies[j];
+                // only the part of the step after either body last merged is searched
+                let t0 = not_before[i].max(not_before[j]);
+                let (start_i, start_j) = (start_i.lerp(end_i, t0), start_j.lerp(end_j, t0));
+                // the pair's relative motion starts from the nearest images, and carries on from there
+                let start = minimum_image(start_j - start_i, period);
+                time_of_contact(
+                    start,
+                    start + (end_j - start_j) - (end_i - start_i),
+                    get_radius(body_i) + get_radius(body_j),
+                )
+                .map(|t| (t0 + t * (1. - t0), i, j))
+            })
+            .collect();
+        contacts.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        // a body merges at most once per pass, later contacts for it are checked again next pass with its new path
+        let mut touched = vec![false; bodies.len()];
+        for (t, i, j) in contacts {
+            if touched[i] || touched[j] {
                 continue;
             }
-
+            // the heavier body survives, so a small body hitting a large one doesn't move the large one's entity
+            let (survivor, other) = if bodies[j].1.mass > bodies[i].1.mass {
+                (j, i)
+            } else {
+                (i, j)
+            };
+            let (_, body_s, start_s, end_s, v_s, spin_s) = bodies[survivor];
+            let (_, body_o, start_o, end_o, v_o, spin_o) = bodies[other];
+            let (m1, m2) = (body_s.mass, body_o.mass);
             let net_mass = m1 + m2;
 
-            // set entity1's position to the center of mass of the two
-            p1.0 = (m1 * p1.0 + m2 * p2.0) / net_mass;
+            let contact_s = start_s.lerp(end_s, t);
+            // the absorbed body joins from its image nearest the survivor
+            let contact_o = contact_s + minimum_image(start_o.lerp(end_o, t) - contact_s, period);
+            let relative_speed = (v_o - v_s).length();
+            collisions.send(CollisionEvent {
+                time: clock.elapsed - (1. - t) * dt,
+                survivor: bodies[survivor].0,
+                absorbed: bodies[other].0,
+                survivor_mass: m1,
+                absorbed_mass: m2,
+                relative_speed,
+                // the kinetic energy of the relative motion is what the merge destroys
+                impact_energy: 0.5 * m1 * m2 / net_mass * relative_speed * relative_speed,
+                location: contact_s
+                    + (contact_o - contact_s).normalize_or_zero() * get_radius(body_s),
+            });
 
-            // add entity2's momentum to entity1
-            v1.0 = (m1 * v1.0 + m2 * v2.0) / net_mass;
-            body1.mass = net_mass;
-
-            // enqueue the removal of entity2 and set its mass to 0 (so it won't collide with anything else)
-            commands.entity(entity2).despawn();
-            body2.mass = 0.;
+            // join at the center of mass at the time of contact, then carry on for the rest of the step with the combined momentum
+            let contact = (m1 * contact_s + m2 * contact_o) / net_mass;
+            let displacement = (m1 * (end_s - start_s) + m2 * (end_o - start_o)) / net_mass;
+            let velocity = (m1 * v_s + m2 * v_o) / net_mass;
+            let merged_body = Body {
+                mass: net_mass,
+                density: merged_density(body_s, body_o),
+            };
+            // the pair's orbital angular momentum about their center of mass becomes spin, along with their own spins
+            let angular_momentum = m1 * (contact_s - contact).cross(v_s - velocity)
+                + m2 * (contact_o - contact).cross(v_o - velocity)
+                + moment_of_inertia(body_s) * spin_s
+                + moment_of_inertia(body_o) * spin_o;
+            bodies[survivor].1 = merged_body;
+            bodies[survivor].2 = contact - displacement * t;
+            bodies[survivor].3 = contact + displacement * (1. - t);
+            bodies[survivor].4 = velocity;
+            bodies[survivor].5 = angular_momentum / moment_of_inertia(merged_body);
+            charges[survivor] += charges[other];
+            merged[survivor] = true;
+            not_before[survivor] = t;
+            absorbed[other] = true;
+            touched[survivor] = true;
+            touched[other] = true;
+        }
+        if !touched.contains(&true) {
+            break;
         }
     }
-}
 
-pub fn update_body_meshes(mut query: Query<(&mut Transform, &Position, &Body)>) {
-    for (mut transform, position, body) in &mut query {
-        transform.translation = position.0;
-        transform.scale = Vec3::ONE * get_radius(*body);
-    }
+    for (k, (entity, body, start, end, velocity, spin)) in bodies.into_iter().enumerate() {
+        if absorbed[k] {
+            commands.entity(entity).despawn();
+        } else if merged[k] {
+            if let Ok((_, mut b, mut p, mut previous, mut v, mut w, charge)) = query.get_mut(entity)
+            {
+                *b = body;
+                p.0 = end;
+                previous.0 = start;
+                v.0 = velocity;
+                w.0 = spin;
+                // its acceleration included the pull of the body it absorbed
+                commands.entity(entity).remove::<Acceleration>();
+                match charge {
+                    Some(mut charge) => charge.0 = charges[k],
+                    None if charges[k] != 0. => {
+                        commands.entity(entity).insert(Charge(charges[k]));
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+}
+
+// draw bodies relative to the camera so that precision is highest where it is visible
+pub fn update_body_meshes(
+    mut query: Query<(
+        &mut Transform,
+        &Position,
+        &Orientation,
+        &Body,
+        Has<TestParticle>,
+    )>,
+    camera: Query<&CameraPosition>,
+    config: Res<Configuration>,
+) {
+    let origin = camera.single().0;
+    for (mut transform, position, orientation, body, particle) in &mut query {
+        transform.translation = (position.0 - origin).as_vec3();
+        transform.rotation = orientation.0.as_quat();
+        transform.scale = Vec3::ONE
+            * if particle {
+                config.test_particle_radius
+            } else {
+                get_radius(*body) as f32
+            };
+    }
+}
+
+pub fn create_collision_log(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                // bottom right, clear of the display down the left, and above the timeline
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(0.),
+                    bottom: Val::Px(90.),
+                    padding: UiRect::all(Val::Px(5.0)),
+                    ..default()
+                },
+                // hidden until the first collision
+                visibility: Visibility::Hidden,
+                z_index: ZIndex::Global(i32::MAX - 1),
+                background_color: Color::WHITE.with_alpha(0.5).into(),
+                ..default()
+            },
+            CollisionLogUI,
+        ))
+        .with_children(|c| {
+            c.spawn((TextBundle::default(), CollisionLogText));
+        });
+}
+
+// add new collisions and escapes to the bottom of the on-screen log, dropping the oldest ones off the top
+pub fn update_collision_log(
+    mut events: EventReader<CollisionEvent>,
+    mut escapes: EventReader<EscapeEvent>,
+    mut log: ResMut<CollisionLog>,
+    mut text: Query<&mut Text, With<CollisionLogText>>,
+    mut ui: Query<&mut Visibility, With<CollisionLogUI>>,
+    config: Res<Configuration>,
+) {
+    if events.is_empty() && escapes.is_empty() {
+        return;
+    }
+    let units = config.units;
+    for event in events.read() {
+        log.0.push_back(format!(
+            "{0}: {1} absorbed {2} (masses {3} + {4}) at {5}, energy {6:.2}",
+            units.duration(event.time),
+            event.survivor,
+            event.absorbed,
+            units.mass(event.survivor_mass),
+            units.mass(event.absorbed_mass),
+            units.speed(event.relative_speed),
+            event.impact_energy,
+        ));
+    }
+    for escape in escapes.read() {
+        log.0.push_back(format!(
+            "{0}: {1} (mass {2}) escaped through ({3}, {4}, {5}) at {6}",
+            units.duration(escape.time),
+            escape.body,
+            units.mass(escape.mass),
+            units.length(escape.position.x),
+            units.length(escape.position.y),
+            units.length(escape.position.z),
+            units.speed(escape.velocity.length()),
+        ));
+    }
+    while log.0.len() > config.collision_log_lines {
+        log.0.pop_front();
+    }
+    text.single_mut().sections = log
+        .0
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 {
+                text_section(Color::BLACK, line)
+            } else {
+                text_section(Color::BLACK, &format!("\n{line}"))
+            }
+        })
+        .collect();
+    *ui.single_mut() = Visibility::Visible;
+}
+
+// seconds an impact flash stays on screen
+const IMPACT_FLASH_DURATION: f32 = 0.6;
+
+pub fn spawn_impact_flashes(
+    mut events: EventReader<CollisionEvent>,
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    sphere_info: Res<SphereInfo>,
+) {
+    for event in events.read() {
+        // the merged body's density isn't known here, the default is close enough for a flash
+        let radius = get_radius(Body {
+            mass: event.survivor_mass + event.absorbed_mass,
+            density: 1.,
+        }) as f32;
+        commands.spawn((
+            ImpactFlash {
+                position: event.location,
+                radius,
+                age: 0.,
+            },
+            PbrBundle {
+                mesh: sphere_info.0.clone(),
+                // every flash fades separately so each gets its own material
+                material: materials.add(StandardMaterial {
+                    base_color: Color::srgb(1., 0.85, 0.4),
+                    unlit: true,
+                    alpha_mode: AlphaMode::Blend,
+                    ..default()
+                }),
+                transform: Transform::from_scale(Vec3::ZERO),
+                ..default()
+            },
+        ));
+    }
+}
+
+// grow and fade impact flashes in real time, so they still play out while paused
+pub fn animate_impact_flashes(
+    mut query: Query<(
+        Entity,
+        &mut ImpactFlash,
+        &mut Transform,
+        &Handle<StandardMaterial>,
+    )>,
+    camera: Query<&CameraPosition>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut commands: Commands,
+    time: Res<Time<Real>>,
+) {
+    let origin = camera.single().0;
+    for (entity, mut flash, mut transform, material) in &mut query {
+        flash.age += time.delta_seconds();
+        if flash.age >= IMPACT_FLASH_DURATION {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let progress = flash.age / IMPACT_FLASH_DURATION;
+        transform.translation = (flash.position - origin).as_vec3();
+        transform.scale = Vec3::ONE * flash.radius * (1. + 2. * progress);
+        if let Some(material) = materials.get_mut(material) {
+            material.base_color.set_alpha(1. - progress);
+        }
+    }
+}
+
+// append collisions to the configured log file as json lines
+pub fn write_collision_events(
+    mut events: EventReader<CollisionEvent>,
+    mut writer: Local<Option<BufWriter<File>>>,
+    mut failed: Local<bool>,
+    config: Res<Configuration>,
+) {
+    let Some(path) = &config.collision_log_file else {
+        events.clear();
+        return;
+    };
+    if events.is_empty() || *failed {
+        events.clear();
+        return;
+    }
+    if writer.is_none() {
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => *writer = Some(BufWriter::new(file)),
+            Err(e) => {
+                println!("Could not open collision log file {path}: {e}");
+                *failed = true;
+                events.clear();
+                return;
+            }
+        }
+    }
+    let file = writer.as_mut().unwrap();
+    for event in events.read() {
+        if let Err(e) = writeln!(file, "{}", event.json_line()) {
+            println!("Could not write to collision log file {path}: {e}");
+        }
+    }
+    if let Err(e) = file.flush() {
+        println!("Could not write to collision log file {path}: {e}");
+    }
+}
+
+// Tab cycles the reference frame and Enter toggles applying it to the bodies' state
+pub fn reference_frame_selection(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut frame: ResMut<ReferenceFrame>,
+    config: Res<Configuration>,
+) {
+    if keys.just_pressed(KeyCode::Tab) {
+        frame.mode = frame.mode.next();
+        frame.last = None;
+    }
+    if keys.just_pressed(KeyCode::Enter) {
+        match recentering_blocker(&config) {
+            Some(reason) if !frame.apply_to_state => {
+                println!("Can't apply the reference frame to the bodies with {reason}")
+            }
+            _ => frame.apply_to_state = !frame.apply_to_state,
+        }
+    }
+}
+
+// what pins the simulation to a frame of its own, so moving the bodies out of it would change the dynamics
+fn recentering_blocker(config: &Configuration) -> Option<&'static str> {
+    if !config.external_potentials.is_empty() {
+        Some("external fields")
+    } else if config.boundary != Boundary::None {
+        Some("a boundary")
+    } else if config.drag != Drag::None && !config.drag_atmosphere {
+        Some("drag from a medium at rest")
+    } else {
+        None
+    }
+}
+
+// the body a body centered or co-rotating frame is built on: the selected body, otherwise the heaviest
+fn frame_anchor<'a>(
+    bodies: impl Iterator<Item = (Entity, &'a Body, &'a Position, &'a Velocity, bool)>,
+) -> Option<(Entity, &'a Body, &'a Position, &'a Velocity, bool)> {
+    // a test particle can't anchor a frame, even a selected one
+    bodies.filter(|(_, body, _, _, _)| body.mass > 0.).max_by(
+        |(_, b1, _, _, selected1), (_, b2, _, _, selected2)| {
+            selected1.cmp(selected2).then(b1.mass.total_cmp(&b2.mass))
+        },
+    )
+}
+
+// the body pulling hardest on the given one, its partner in a binary
+fn companion<'a>(
+    bodies: impl Iterator<Item = (Entity, &'a Body, &'a Position, &'a Velocity, bool)>,
+    entity: Entity,
+    position: DVec3,
+) -> Option<(Entity, &'a Body, &'a Position, &'a Velocity, bool)> {
+    bodies
+        .filter(|(other, body, p, _, _)| *other != entity && body.mass > 0. && p.0 != position)
+        .max_by(|(_, b1, p1, _, _), (_, b2, p2, _, _)| {
+            (b1.mass / p1.0.distance_squared(position))
+                .total_cmp(&(b2.mass / p2.0.distance_squared(position)))
+        })
+}
+
+// subtract the frame's motion from every body so the barycenter or anchor body stays at rest at the origin
+// relative motion, and so the dynamics, are unchanged, and the camera, spawn tools, impact flashes and the
+// rewind history are shifted along
+// it stops while anything fixes a frame of its own, like a box or an external field
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+pub fn recenter_bodies(
+    mut bodies: Query<(
+        Entity,
+        &Body,
+        &mut Position,
+        &mut PreviousPosition,
+        &mut Velocity,
+        Has<Selected>,
+    )>,
+    mut camera: Query<&mut CameraPosition>,
+    mut spawn_options: ResMut<BodySpawningOptions>,
+    mut slingshot: ResMut<Slingshot>,
+    mut flashes: Query<&mut ImpactFlash>,
+    mut rewind: ResMut<Rewind>,
+    frame: Res<ReferenceFrame>,
+    clock: Res<SimulationClock>,
+    config: Res<Configuration>,
+) {
+    if !frame.apply_to_state || recentering_blocker(&config).is_some() {
+        return;
+    }
+    let (shift, boost) = match frame.mode {
+        FrameMode::Barycentric => {
+            let (position, velocity, _) =
+                center_of_mass(bodies.iter().map(|(_, body, position, _, velocity, _)| {
+                    (body.mass, position.0, velocity.0)
+                }));
+            (position, velocity)
+        }
+        FrameMode::BodyCentered => {
+            match frame_anchor(bodies.iter().map(|(e, b, p, _, v, s)| (e, b, p, v, s))) {
+                Some((_, _, position, velocity, _)) => (position.0, velocity.0),
+                None => return,
+            }
+        }
+        // rotating the state would add fictitious forces, so those frames are display only
+        FrameMode::Inertial | FrameMode::CoRotating => return,
+    };
+    if shift == DVec3::ZERO && boost == DVec3::ZERO {
+        return;
+    }
+    for (_, _, mut position, mut previous, mut velocity, _) in &mut bodies {
+        position.0 -= shift;
+        previous.0 -= shift;
+        velocity.0 -= boost;
+    }
+    camera.single_mut().0 -= shift;
+    if let Some(pinned) = &mut spawn_options.pinned_position {
+        *pinned -= shift;
+    }
+    if let Some(anchor) = &mut slingshot.anchor {
+        *anchor -= shift;
+    }
+    for mut flash in &mut flashes {
+        flash.position -= shift;
+    }
+    // the recorded past is moved into the same frame, which has been moving at the boost all along
+    for snapshot in &mut rewind.snapshots {
+        let offset = shift + boost * (snapshot.elapsed - clock.elapsed);
+        for (_, state, ..) in &mut snapshot.bodies {
+            state.position -= offset;
+            state.velocity -= boost;
+        }
+    }
+}
+
+// work out where the reference frame is now and carry the camera along with it since the last update
+pub fn follow_reference_frame(
+    bodies: Query<(Entity, &Body, &Position, &Velocity, Has<Selected>)>,
+    mut camera: Query<(&mut Transform, &mut CameraPosition), With<Camera>>,
+    mut frame: ResMut<ReferenceFrame>,
+) {
+    // (body the frame is built on, origin, rotation, velocity, angular velocity)
+    let current = match frame.mode {
+        FrameMode::Inertial => Some((
+            Entity::PLACEHOLDER,
+            DVec3::ZERO,
+            DQuat::IDENTITY,
+            DVec3::ZERO,
+            DVec3::ZERO,
+        )),
+        FrameMode::Barycentric => {
+            let (position, velocity, _) = center_of_mass(
+                bodies
+                    .iter()
+                    .map(|(_, body, position, velocity, _)| (body.mass, position.0, velocity.0)),
+            );
+            Some((
+                Entity::PLACEHOLDER,
+                position,
+                DQuat::IDENTITY,
+                velocity,
+                DVec3::ZERO,
+            ))
+        }
+        FrameMode::BodyCentered => frame_anchor(bodies.iter())
+            .map(|(entity, _, p, v, _)| (entity, p.0, DQuat::IDENTITY, v.0, DVec3::ZERO)),
+        FrameMode::CoRotating => {
+            frame_anchor(bodies.iter()).and_then(|(entity, body, p1, v1, _)| {
+                let (_, companion, p2, v2, _) = companion(bodies.iter(), entity, p1.0)?;
+                let (origin, velocity, _) = center_of_mass(
+                    [(body.mass, p1.0, v1.0), (companion.mass, p2.0, v2.0)].into_iter(),
+                );
+                let separation = p2.0 - p1.0;
+                let orbital = separation.cross(v2.0 - v1.0);
+                let x = separation.normalize();
+                let z = orbital
+                    .try_normalize()
+                    .unwrap_or(x.any_orthonormal_vector());
+                let rotation = DQuat::from_mat3(&DMat3::from_cols(x, z.cross(x), z));
+                Some((
+                    entity,
+                    origin,
+                    rotation,
+                    velocity,
+                    orbital / separation.length_squared(),
+                ))
+            })
+        }
+    };
+    let Some((anchor, origin, rotation, velocity, angular_velocity)) = current else {
+        frame.last = None;
+        return;
+    };
+    frame.origin = origin;
+    frame.rotation = rotation;
+    frame.velocity = velocity;
+    frame.angular_velocity = angular_velocity;
+    // only follow a continuous frame, switching modes or bodies leaves the camera where it is
+    if let Some((mode, last_anchor, last_origin, last_rotation)) = frame.last {
+        if mode == frame.mode && last_anchor == anchor {
+            let turn = rotation * last_rotation.inverse();
+            let (mut transform, mut camera_position) = camera.single_mut();
+            camera_position.0 = origin + turn * (camera_position.0 - last_origin);
+            transform.rotation = turn.as_quat() * transform.rotation;
+        }
+    }
+    frame.last = Some((frame.mode, anchor, origin, rotation));
+}
+
+// break up bodies that come inside the Roche limit of a much heavier one into a stream of equal fragments
+// along its orbit, see tidal_fragments
+// fragments start inside the same Roche limit, so they only break up again on a later pass
+#[allow(clippy::type_complexity)]
+pub fn tidal_disruption(
+    query: Query<(
+        Entity,
+        &Body,
+        &Position,
+        &Velocity,
+        &Spin,
+        Option<&Charge>,
+        Has<TidalFragment>,
+    )>,
+    mut commands: Commands,
+    sphere_info: Res<SphereInfo>,
+    config: Res<Configuration>,
+) {
+    if !config.tidal_disruption || config.tidal_fragments < 2 {
+        return;
+    }
+    let fragments = config.tidal_fragments;
+    let period = config.period();
+    for (entity, body, position, velocity, spin, charge, is_fragment) in &query {
+        let fragment = Body {
+            mass: body.mass / fragments as f64,
+            density: body.density,
+        };
+        if fragment.mass < config.tidal_fragment_min_mass {
+            continue;
+        }
+        // to the nearest image in a periodic box
+        let distance = |p: &Position| minimum_image(p.0 - position.0, period).length();
+        // the heavy body pulling hardest on the near and far sides, whose Roche limit the body is inside
+        let primary = query
+            .iter()
+            .filter(|(other, primary, p, ..)| {
+                *other != entity
+                    && primary.mass >= config.tidal_mass_ratio * body.mass
+                    && distance(p) < roche_limit(**primary, *body)
+            })
+            .max_by(|(_, b1, p1, ..), (_, b2, p2, ..)| {
+                (b1.mass / distance(p1).powi(3)).total_cmp(&(b2.mass / distance(p2).powi(3)))
+            });
+        if is_fragment {
+            if primary.is_none() {
+                commands.entity(entity).remove::<TidalFragment>();
+            }
+            continue;
+        }
+        let Some((_, primary, primary_position, primary_velocity, ..)) = primary else {
+            continue;
+        };
+        let Some(stream) = tidal_fragments(
+            body_state(body, position, velocity, spin, charge),
+            minimum_image(position.0 - primary_position.0, period),
+            velocity.0 - primary_velocity.0,
+            get_radius(*primary),
+            fragments,
+        ) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        for state in stream {
+            let fragment = spawn_body(&mut commands, state, &sphere_info);
+            commands.entity(fragment).insert(TidalFragment);
+        }
+    }
+}
+
+// number keys toggle optional physics
+pub fn toggle_physics(keys: Res<ButtonInput<KeyCode>>, mut config: ResMut<Configuration>) {
+    if keys.just_pressed(KeyCode::Digit1) {
+        config.post_newtonian = !config.post_newtonian;
+    }
+    if keys.just_pressed(KeyCode::Digit2) {
+        config.radiation_reaction = !config.radiation_reaction;
+    }
+    if keys.just_pressed(KeyCode::Digit3) {
+        config.tidal_disruption = !config.tidal_disruption;
+    }
+    if keys.just_pressed(KeyCode::Digit4) {
+        config.boundary = config.boundary.next();
+    }
+    if keys.just_pressed(KeyCode::Digit5) {
+        config.gravity_law = config.gravity_law.next();
+    }
+    if keys.just_pressed(KeyCode::Digit6) {
+        config.drag = config.drag.next();
+    }
+    if keys.just_pressed(KeyCode::Digit7) {
+        config.drag_atmosphere = !config.drag_atmosphere;
+    }
+    if keys.just_pressed(KeyCode::Digit8) {
+        config.show_energy = !config.show_energy;
+    }
+}
+
+pub fn create_gravitational_wave_overlay(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(0.),
+                    top: Val::Px(0.),
+                    padding: UiRect::all(Val::Px(5.0)),
+                    ..default()
+                },
+                // hidden until a body is selected
+                visibility: Visibility::Hidden,
+                z_index: ZIndex::Global(i32::MAX - 1),
+                background_color: Color::WHITE.with_alpha(0.5).into(),
+                ..default()
+            },
+            GravitationalWaveUI,
+        ))
+        .with_children(|c| {
+            c.spawn((
+                TextBundle::from_sections([
+                    text_section(Color::BLACK, "Binary: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nTime to merger: "),
+                    text_section(Color::BLACK, ""),
+                    text_section(Color::BLACK, "\nStrain: "),
+                    text_section(Color::BLACK, ""),
+                ]),
+                GravitationalWaveText,
+            ));
+        });
+}
+
+// follow the selected body and its companion as a binary and record the strain their gravitational waves
+// would have at the camera after every physics step, with the simulation time it was taken at
+// going back in time, by running backwards or rewinding, drops the samples from the future
+pub fn record_strain(
+    bodies: Query<(Entity, &Body, &Position, &Velocity, Has<Selected>)>,
+    camera: Query<(&Transform, &CameraPosition), With<Camera>>,
+    mut waves: ResMut<GravitationalWaves>,
+    clock: Res<SimulationClock>,
+    config: Res<Configuration>,
+) {
+    let binary = bodies
+        .iter()
+        .find(|(_, body, _, _, selected)| *selected && body.mass > 0.)
+        .and_then(|primary| {
+            let (entity, _, position, _, _) = primary;
+            Some((primary, companion(bodies.iter(), entity, position.0)?))
+        });
+    let Some(((e1, b1, p1, v1, _), (e2, b2, p2, v2, _))) = binary else {
+        waves.pair = None;
+        waves.strain.clear();
+        return;
+    };
+    if waves.pair != Some((e1, e2)) {
+        waves.pair = Some((e1, e2));
+        waves.strain.clear();
+    }
+    while waves
+        .strain
+        .back()
+        .is_some_and(|(time, _)| *time >= clock.elapsed)
+    {
+        waves.strain.pop_back();
+    }
+    if clock.dt == 0. {
+        return;
+    }
+    let (tf, camera_position) = camera.single();
+    let (center, _, _) = center_of_mass([(b1.mass, p1.0, v1.0), (b2.mass, p2.0, v2.0)].into_iter());
+    let h = strain(
+        (b1.mass, b2.mass),
+        p2.0 - p1.0,
+        v2.0 - v1.0,
+        center.distance(camera_position.0),
+        (tf.right().as_dvec3(), tf.up().as_dvec3()),
+        config.gravity_constant,
+        config.speed_of_light,
+    );
+    waves.strain.push_back((clock.elapsed, h));
+    while waves.strain.len() > config.waveform_samples {
+        waves.strain.pop_front();
+    }
+}
+
+// show the binary being followed, when gravitational waves will merge it and its latest strain
+pub fn update_gravitational_waves(
+    bodies: Query<(&Body, &Position, &Velocity)>,
+    mut text: Query<&mut Text, With<GravitationalWaveText>>,
+    mut ui: Query<&mut Visibility, With<GravitationalWaveUI>>,
+    waves: Res<GravitationalWaves>,
+    config: Res<Configuration>,
+) {
+    let binary = waves
+        .pair
+        .and_then(|(e1, e2)| Some(((e1, bodies.get(e1).ok()?), (e2, bodies.get(e2).ok()?))));
+    let Some(((e1, (b1, p1, v1)), (e2, (b2, p2, v2)))) = binary else {
+        *ui.single_mut() = Visibility::Hidden;
+        return;
+    };
+    let mut text = text.single_mut();
+    text.sections[1].value = format!("{e1} and {e2}");
+    text.sections[3].value = match merger_time(
+        (b1.mass, b2.mass),
+        p2.0 - p1.0,
+        v2.0 - v1.0,
+        config.gravity_constant,
+        config.speed_of_light,
+    ) {
+        Some(t) => config.units.duration(t),
+        None => "unbound".into(),
+    };
+    text.sections[5].value = format!("{0:.3e}", waves.strain.back().map_or(0., |(_, h)| *h));
+    *ui.single_mut() = Visibility::Visible;
+}
+
+// draw the recorded strain against simulation time as a trace along the bottom right of the view,
+// scaled to its largest value
+pub fn draw_waveform(
+    mut gizmos: Gizmos,
+    camera: Query<&Transform, With<Camera>>,
+    waves: Res<GravitationalWaves>,
+) {
+    let largest = waves
+        .strain
+        .iter()
+        .fold(0., |m: f64, (_, h)| m.max(h.abs()));
+    let (Some((start, _)), Some((end, _))) = (waves.strain.front(), waves.strain.back()) else {
+        return;
+    };
+    if end <= start || largest == 0. {
+        return;
+    }
+    // the camera sits at the render origin, so points just in front of it stay fixed on screen
+    let tf = camera.single();
+    let width = 0.4;
+    let height = 0.06;
+    let color = Color::srgb(0.4, 0.85, 1.);
+    gizmos.linestrip(
+        waves.strain.iter().map(|(t, h)| {
+            let x = 0.1 + width * ((t - start) / (end - start)) as f32;
+            let y = -0.3 + height * (h / largest) as f32;
+            tf.rotation * Vec3::new(x, y, -1.)
+        }),
+        color,
+    );
+}
+
+// keep the state after every physics step that moved anything, so it can be rewound to
+#[allow(clippy::type_complexity)]
+pub fn record_snapshot(
+    query: Query<(
+        Entity,
+        &Body,
+        &Position,
+        &Velocity,
+        Option<&Acceleration>,
+        &Spin,
+        &Orientation,
+        Option<&Charge>,
+    )>,
+    mut rewind: ResMut<Rewind>,
+    clock: Res<SimulationClock>,
+    dissipation: Res<Dissipation>,
+    config: Res<Configuration>,
+) {
+    if clock.dt == 0. {
+        return;
+    }
+    let mut bodies = std::mem::take(&mut rewind.spare);
+    bodies.clear();
+    // test particles can't be rewound, there can be far too many of them to keep
+    bodies.extend(query.iter().filter(|(_, body, ..)| body.mass > 0.).map(
+        |(entity, body, position, velocity, acceleration, spin, orientation, charge)| {
+            (
+                entity,
+                body_state(body, position, velocity, spin, charge),
+                acceleration.map(|acceleration| acceleration.0),
+                orientation.0,
+            )
+        },
+    ));
+    rewind.record(
+        Snapshot {
+            elapsed: clock.elapsed,
+            drag: dissipation.drag,
+            bodies,
+        },
+        config.rewind_length,
+        config.rewind_body_limit,
+    );
+}
+
+// holding a bracket pauses and steps back or forward through the recorded states, writing them back into the bodies
+// bodies that didn't exist then are removed and ones that have since merged, broken up or escaped are brought back,
+// test particles are left alone, and stepping on from there continues from the rewound state
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+pub fn scrub_timeline(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut bodies: Query<(
+        Entity,
+        &mut Body,
+        &mut Position,
+        &mut PreviousPosition,
+        &mut Velocity,
+        &mut Spin,
+        &mut Orientation,
+    )>,
+    mut commands: Commands,
+    sphere_info: Res<SphereInfo>,
+    mut rewind: ResMut<Rewind>,
+    mut history: ResMut<EditHistory>,
+    mut clock: ResMut<SimulationClock>,
+    mut dissipation: ResMut<Dissipation>,
+    mut time: ResMut<Time<Virtual>>,
+    config: Res<Configuration>,
+) {
+    let Some(latest) = rewind.snapshots.len().checked_sub(1) else {
+        return;
+    };
+    // shift scrubs faster
+    let steps = if keys.pressed(KeyCode::ShiftLeft) {
+        config.speed_mod_factor as usize
+    } else {
+        1
+    };
+    // the latest snapshot is where the world is when it hasn't been rewound
+    let current = rewind.cursor.unwrap_or(latest);
+    let target = if keys.pressed(KeyCode::BracketLeft) {
+        current.saturating_sub(steps)
+    } else if keys.pressed(KeyCode::BracketRight) {
+        (current + steps).min(latest)
+    } else {
+        return;
+    };
+    time.pause();
+    if target == current {
+        return;
+    }
+    rewind.cursor = Some(target);
+    let snapshot = &rewind.snapshots[target];
+    let recorded: HashMap<Entity, usize> = snapshot
+        .bodies
+        .iter()
+        .enumerate()
+        .map(|(i, (entity, ..))| (*entity, i))
+        .collect();
+    // bodies removed or brought back, which edits in the undo history may refer to
+    let mut replaced = Vec::new();
+    let mut restored = vec![false; snapshot.bodies.len()];
+    for (entity, mut body, mut position, mut previous, mut velocity, mut spin, mut orientation) in
+        &mut bodies
+    {
+        let Some(&i) = recorded.get(&entity) else {
+            // test particles aren't recorded and stay as they are
+            if body.mass == 0. {
+                continue;
+            }
+            commands.entity(entity).despawn();
+            replaced.push(entity);
+            continue;
+        };
+        let (_, state, acceleration, turned) = snapshot.bodies[i];
+        *body = state.body;
+        position.0 = state.position;
+        previous.0 = state.position;
+        velocity.0 = state.velocity;
+        spin.0 = state.spin;
+        orientation.0 = turned;
+        let mut entity_commands = commands.entity(entity);
+        match acceleration {
+            Some(acceleration) => entity_commands.insert(Acceleration(acceleration)),
+            None => entity_commands.remove::<Acceleration>(),
+        };
+        if state.charge != 0. {
+            entity_commands.insert(Charge(state.charge));
+        } else {
+            entity_commands.remove::<Charge>();
+        }
+        restored[i] = true;
+    }
+    let mut respawned = Vec::new();
+    for (i, (entity, state, acceleration, turned)) in snapshot.bodies.iter().enumerate() {
+        if restored[i] {
+            continue;
+        }
+        let new = spawn_body(&mut commands, *state, &sphere_info);
+        let mut entity_commands = commands.entity(new);
+        entity_commands.insert(Orientation(*turned));
+        if let Some(acceleration) = acceleration {
+            entity_commands.insert(Acceleration(*acceleration));
+        }
+        replaced.push(*entity);
+        respawned.push((*entity, new));
+    }
+    clock.elapsed = snapshot.elapsed;
+    dissipation.drag = snapshot.drag;
+    for (old, new) in respawned {
+        rewind.remap(old, new);
+    }
+    // undoing an edit to a body that the rewind removed or brought back would lose or duplicate it
+    if replaced.iter().any(|entity| history.refers_to(*entity)) {
+        history.clear();
+        println!("Undo history cleared, rewinding removed or brought back bodies it refers to");
+    }
+}
+
+pub fn create_timeline(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Percent(25.),
+                    width: Val::Percent(50.),
+                    bottom: Val::Px(10.),
+                    padding: UiRect::all(Val::Px(5.0)),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                // only shown while paused
+                visibility: Visibility::Hidden,
+                z_index: ZIndex::Global(i32::MAX - 1),
+                background_color: Color::WHITE.with_alpha(0.5).into(),
+                ..default()
+            },
+            TimelineUI,
+        ))
+        .with_children(|c| {
+            c.spawn((
+                TextBundle::from_sections([
+                    text_section(Color::BLACK, "Timeline: "),
+                    text_section(Color::BLACK, ""),
+                ]),
+                TimelineText,
+            ));
+            c.spawn(NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.),
+                    height: Val::Px(12.),
+                    ..default()
+                },
+                background_color: Color::BLACK.with_alpha(0.5).into(),
+                ..default()
+            })
+            .with_children(|c| {
+                c.spawn((
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(100.),
+                            height: Val::Percent(100.),
+                            ..default()
+                        },
+                        background_color: Color::srgb(1., 0., 0.).into(),
+                        ..default()
+                    },
+                    TimelineFill,
+                ));
+            });
+        });
+}
+
+// show how far back through the recorded steps the world is, while paused
+pub fn update_timeline(
+    mut ui: Query<&mut Visibility, With<TimelineUI>>,
+    mut text: Query<&mut Text, With<TimelineText>>,
+    mut fill: Query<&mut Style, With<TimelineFill>>,
+    rewind: Res<Rewind>,
+    clock: Res<SimulationClock>,
+    time: Res<Time<Virtual>>,
+    config: Res<Configuration>,
+) {
+    let Some(latest) = rewind.snapshots.len().checked_sub(1) else {
+        *ui.single_mut() = Visibility::Hidden;
+        return;
+    };
+    *ui.single_mut() = if time.is_paused() {
+        Visibility::Visible
+    } else {
+        Visibility::Hidden
+    };
+    let current = rewind.cursor.unwrap_or(latest);
+    text.single_mut().sections[1].value = format!(
+        "{0}, {1} of {2} steps back",
+        config.units.duration(clock.elapsed),
+        latest - current,
+        latest
+    );
+    fill.single_mut().width = if latest == 0 {
+        Val::Percent(100.)
+    } else {
+        Val::Percent(100. * current as f32 / latest as f32)
+    };
 }
@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+
+use crate::helpers::volume_density;
+
+// physical constants in SI units
+const GRAVITY_CONSTANT: f64 = 6.6743e-11;
+const SPEED_OF_LIGHT: f64 = 299_792_458.;
+const COULOMB_CONSTANT: f64 = 8.987_551_792_3e9;
+
+const ASTRONOMICAL_UNIT: f64 = 1.495_978_707e11;
+const SOLAR_MASS: f64 = 1.988_47e30;
+const DAY: f64 = 86_400.;
+// julian year
+const YEAR: f64 = 365.25 * DAY;
+
+// the gravity constant in simulation units, which the built-in scene and the configuration defaults are set up for
+pub const SCENE_GRAVITY_CONSTANT: f64 = 8.;
+
+// what one unit of length, mass and time in the simulation stands for
+// in simulation units nothing is physical and the constants are whatever the configuration says,
+// otherwise they are derived from their SI values
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitSystem {
+    #[default]
+    Simulation,
+    // metres, kilograms and seconds
+    Si,
+    // astronomical units, solar masses and days
+    Astronomical,
+}
+
+impl UnitSystem {
+    // metres, kilograms and seconds in one simulation unit of length, mass and time
+    fn scales(self) -> Option<(f64, f64, f64)> {
+        match self {
+            Self::Simulation => None,
+            Self::Si => Some((1., 1., 1.)),
+            Self::Astronomical => Some((ASTRONOMICAL_UNIT, SOLAR_MASS, DAY)),
+        }
+    }
+
+    pub fn gravity_constant(self) -> Option<f64> {
+        let (length, mass, time) = self.scales()?;
+        Some(GRAVITY_CONSTANT * mass * time * time / length.powi(3))
+    }
+
+    pub fn speed_of_light(self) -> Option<f64> {
+        let (length, _, time) = self.scales()?;
+        Some(SPEED_OF_LIGHT * time / length)
+    }
+
+    // charges are in coulombs
+    pub fn coulomb_constant(self) -> Option<f64> {
+        let (length, mass, time) = self.scales()?;
+        Some(COULOMB_CONSTANT * time * time / (mass * length.powi(3)))
+    }
+
+    // length, mass and time in this system's units that one unit of the built-in scene stands for,
+    // with the time following from the other two so the scene moves the same as in simulation units:
+    // roughly earth sized planets in SI, and sun sized stars an AU apart in astronomical units
+    pub fn scene(self) -> (f64, f64, f64) {
+        let (length, mass): (f64, f64) = match self {
+            Self::Simulation => return (1., 1., 1.),
+            Self::Si => (1e7, 1e25),
+            Self::Astronomical => (1., 1.),
+        };
+        let gravity_constant = self.gravity_constant().unwrap_or(SCENE_GRAVITY_CONSTANT);
+        let time = (SCENE_GRAVITY_CONSTANT * length.powi(3) / (gravity_constant * mass)).sqrt();
+        (length, mass, time)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Simulation => "simulation units",
+            Self::Si => "SI (m, kg, s)",
+            Self::Astronomical => "astronomical (AU, solar masses, days)",
+        }
+    }
+
+    pub fn length(self, x: f64) -> String {
+        match self {
+            Self::Simulation => format!("{x:.2} u"),
+            Self::Si => format!("{x:.3e} m"),
+            Self::Astronomical => format!("{x:.3} AU"),
+        }
+    }
+
+    pub fn mass(self, m: f64) -> String {
+        match self {
+            Self::Simulation => format!("{m:.2}"),
+            Self::Si => format!("{m:.3e} kg"),
+            Self::Astronomical => format!("{m:.3e} solar masses"),
+        }
+    }
+
+    // bodies' densities are relative to the default sphere, in physical units the actual density is shown in g/cm³
+    pub fn density(self, density: f64) -> String {
+        match self.scales() {
+            None => format!("{density:.2}"),
+            Some((length, mass, _)) => format!(
+                "{0:.3} g/cm³",
+                volume_density(density) * mass / length.powi(3) / 1e3
+            ),
+        }
+    }
+
+    // speeds are shown in km/s in both physical systems
+    pub fn speed(self, v: f64) -> String {
+        match self.scales() {
+            None => format!("{v:.2} u/s"),
+            Some((length, _, time)) => format!("{0:.3} km/s", v * length / time / 1e3),
+        }
+    }
+
+    // years, days and the time of day, for the mission clock and anything else that can take a while
+    pub fn duration(self, t: f64) -> String {
+        let Some((_, _, time)) = self.scales() else {
+            return if t.abs() < 1e5 {
+                format!("{t:.2} s")
+            } else {
+                format!("{t:.3e} s")
+            };
+        };
+        let sign = if t < 0. { "-" } else { "" };
+        let seconds = (t * time).abs();
+        // nothing would fit on the screen otherwise
+        if seconds >= 1e6 * YEAR {
+            return format!("{sign}{0:.3e} years", seconds / YEAR);
+        }
+        // whole seconds, so the fields can't round past their limits
+        let seconds = seconds as u64;
+        let (years, seconds) = (seconds / YEAR as u64, seconds % YEAR as u64);
+        let (days, seconds) = (seconds / DAY as u64, seconds % DAY as u64);
+        let time_of_day = format!(
+            "{0:02}:{1:02}:{2:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        );
+        if years > 0 {
+            format!("{sign}{years}y {days}d {time_of_day}")
+        } else {
+            format!("{sign}{days}d {time_of_day}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn astronomical_gravity_constant_is_gaussian() {
+        // the square of the Gaussian gravitational constant, in AU^3 / (solar mass day^2)
+        let k = 0.017_202_098_95;
+        let g = UnitSystem::Astronomical.gravity_constant().unwrap();
+        assert!((g / (k * k) - 1.).abs() < 1e-4, "{g}");
+    }
+
+    #[test]
+    fn scene_keeps_the_simulation_gravity_constant() {
+        for units in [UnitSystem::Si, UnitSystem::Astronomical] {
+            let (length, mass, time) = units.scene();
+            let g = units.gravity_constant().unwrap() * mass * time * time / length.powi(3);
+            assert!((g - SCENE_GRAVITY_CONSTANT).abs() < 1e-9, "{g}");
+        }
+    }
+
+    #[test]
+    fn duration_rolls_over() {
+        let units = UnitSystem::Astronomical;
+        assert_eq!(units.duration(0.), "0d 00:00:00");
+        assert_eq!(units.duration(1.5), "1d 12:00:00");
+        assert_eq!(units.duration(1. - 0.5 / DAY), "0d 23:59:59");
+        assert_eq!(units.duration(365.25), "1y 0d 00:00:00");
+        assert_eq!(UnitSystem::Si.duration(3599.9), "0d 00:59:59");
+        assert_eq!(
+            UnitSystem::Si.duration(YEAR + 2. * DAY + 61.),
+            "1y 2d 00:01:01"
+        );
+    }
+
+    #[test]
+    fn negative_durations() {
+        assert_eq!(UnitSystem::Astronomical.duration(-1.5), "-1d 12:00:00");
+        assert_eq!(UnitSystem::Si.duration(-YEAR - 1.), "-1y 0d 00:00:01");
+        assert_eq!(UnitSystem::Simulation.duration(-2.), "-2.00 s");
+    }
+
+    #[test]
+    fn long_durations_use_scientific_notation() {
+        assert_eq!(UnitSystem::Simulation.duration(1.5e9), "1.500e9 s");
+        assert_eq!(UnitSystem::Si.duration(-2e6 * YEAR), "-2.000e6 years");
+    }
+}